@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use crate::cmd::profile::get_or_create_data_dir;
+use crate::cmd::{Cache, CacheCommand, Clear, Run, Stats};
+use crate::search::cache::MetadataCache;
+
+pub fn metadata_cache_path() -> Result<PathBuf> {
+    Ok(get_or_create_data_dir("cache")?.join("metadata.json"))
+}
+
+impl Run for Cache {
+    fn run(&mut self) -> Result<()> {
+        let cmd = self.cmd.as_mut().context("No subcommand provided")?;
+
+        cmd.run()?;
+
+        Ok(())
+    }
+}
+
+impl Run for CacheCommand {
+    fn run(&mut self) -> Result<()> {
+        match self {
+            CacheCommand::Clear(cmd) => cmd.run(),
+            CacheCommand::Stats(cmd) => cmd.run(),
+        }
+    }
+}
+
+impl Run for Clear {
+    fn run(&mut self) -> Result<()> {
+        let path = metadata_cache_path()?;
+
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+
+        println!("Metadata cache cleared");
+
+        Ok(())
+    }
+}
+
+impl Run for Stats {
+    fn run(&mut self) -> Result<()> {
+        let path = metadata_cache_path()?;
+        let size = fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+
+        let cache = MetadataCache::load(&path, self.cache_ttl);
+        let (fresh, expired) = cache.stats();
+
+        println!("Cache file: {:?}", path);
+        println!("  - size: {} KB", size / 1024);
+        println!("  - entries: {}", fresh + expired);
+        println!("  - fresh: {}", fresh);
+        println!("  - expired: {} (ttl {}h)", expired, self.cache_ttl);
+
+        Ok(())
+    }
+}
@@ -2,11 +2,28 @@ use std::path::PathBuf;
 
 use clap::{Parser, Subcommand, ValueEnum, ValueHint};
 
+use crate::cmd::conflict::Conflict;
+use crate::cmd::serve::{DEFAULT_ADDRESS, DEFAULT_HISTORY};
+use crate::cmd::sort::DEFAULT_MIN_CONFIDENCE;
+use crate::cmd::transfer::Transfer;
+use crate::cmd::watch::DEFAULT_SETTLE_TIME;
+use crate::naming::Layout;
+use crate::rules::Rules;
+use crate::search::cache::DEFAULT_CACHE_TTL;
+use crate::search::search::DEFAULT_ACCURACY_THRESHOLD;
+
 #[derive(Parser, Debug)]
 #[clap(about, author, version)]
 pub enum Cmd {
-    Sort(Sort),
+    Sort(Box<Sort>),
     Profile(Profile),
+    Undo(Undo),
+    Cache(Cache),
+    Serve(Serve),
+    Dedupe(Dedupe),
+    Library(Library),
+    Missing(Missing),
+    Parse(Parse),
 }
 
 /// Sort input media files into output directories.
@@ -14,9 +31,22 @@ pub enum Cmd {
 #[clap(about, author)]
 pub struct Sort {
     /// Profile name.
-    #[clap(short, long, exclusive(true))]
+    #[clap(short, long, conflicts_with_all = ["input", "output", "verbose", "threads", "webhook", "recursive", "lookup", "lookup_threshold", "providers", "offline", "cache_ttl", "transfer", "conflict", "trash_dir", "layout", "series_template", "movie_template", "rules", "min_confidence"])]
     pub profile: Option<String>,
 
+    /// Torrent client category, sorted with the profile listing it in its `categories` flag
+    /// or named after it, the command line options are used when there is none.
+    #[clap(long, conflicts_with = "profile")]
+    pub category: Option<String>,
+
+    /// Only sort this file or folder, e.g. a finished download, instead of the whole input.
+    #[clap(long, value_hint = ValueHint::AnyPath, conflicts_with = "watch")]
+    pub single: Option<PathBuf>,
+
+    /// Name of the torrent `--single` belongs to, completes what the file names lack.
+    #[clap(long, requires = "single")]
+    pub torrent_name: Option<String>,
+
     /// Input media files.
     #[clap(short, long, value_hint = ValueHint::DirPath)]
     pub input: Option<PathBuf>,
@@ -40,6 +70,253 @@ pub struct Sort {
     /// Recursive folders scan.
     #[clap(long)]
     pub recursive: bool,
+
+    /// Print the planned moves without touching the filesystem.
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Keep running and sort new media files as they land in the input directory.
+    #[clap(long, conflicts_with = "dry_run")]
+    pub watch: bool,
+
+    /// Seconds a watched file must stay unchanged before it is sorted.
+    #[clap(long, default_value_t = DEFAULT_SETTLE_TIME)]
+    pub settle_time: u64,
+
+    /// Resolve series names through the metadata backends.
+    #[clap(long)]
+    pub lookup: bool,
+
+    /// Minimum accuracy for a lookup result to replace the parsed name.
+    #[clap(long, default_value_t = DEFAULT_ACCURACY_THRESHOLD)]
+    pub lookup_threshold: i64,
+
+    /// Metadata providers to ask in order, e.g. `anilist,tmdb,tvmaze`.
+    #[clap(long, value_delimiter = ',')]
+    pub providers: Option<Vec<String>>,
+
+    /// Only use cached metadata, never query the providers.
+    #[clap(long)]
+    pub offline: bool,
+
+    /// Time to live of cached metadata, in hours.
+    #[clap(long, default_value_t = DEFAULT_CACHE_TTL)]
+    pub cache_ttl: u64,
+
+    /// TMDB API key or read access token, enables movie lookups.
+    #[clap(long, env = "TMDB_API_KEY", hide_env_values = true)]
+    pub tmdb_api_key: Option<String>,
+
+    /// TMDB API base URL.
+    #[clap(long, env = "TMDB_API_URL")]
+    pub tmdb_api_url: Option<String>,
+
+    /// Journal of the last run, when something was moved.
+    #[clap(skip)]
+    pub journal: Option<PathBuf>,
+
+    /// How files get to the library, `copy` or `hardlink` keep the source for seeding.
+    #[clap(long, value_enum)]
+    pub transfer: Option<Transfer>,
+
+    /// What to do when a file is already in the library, `upgrade` keeps the best quality.
+    #[clap(long, value_enum)]
+    pub conflict: Option<Conflict>,
+
+    /// Where replaced files go, `.trash` in the output directory by default.
+    #[clap(long, value_hint = ValueHint::DirPath)]
+    pub trash_dir: Option<PathBuf>,
+
+    /// Naming layout, overridden by the path templates.
+    #[clap(long, value_enum)]
+    pub layout: Option<Layout>,
+
+    /// Series path template, e.g. `{series}/Season {season:02}/{series} S{season:02}E{episode:02}[ - {title}].{ext}`.
+    #[clap(long)]
+    pub series_template: Option<String>,
+
+    /// Movie path template, e.g. `Movies/{series}[ ({year})]/{series}[ ({year})].{ext}`.
+    #[clap(long)]
+    pub movie_template: Option<String>,
+
+    /// Parsing rules file, `rules.json` in the MediaSort data folder by default.
+    #[clap(long, value_hint = ValueHint::FilePath)]
+    pub rules: Option<PathBuf>,
+
+    /// Rules of the `rules` file, loaded when the sort starts.
+    #[clap(skip)]
+    pub parse_rules: Rules,
+
+    /// Confidence, from 0 to 100, needed to sort a file as a movie or an episode, the others go to `Unsorted/`.
+    #[clap(long, default_value_t = DEFAULT_MIN_CONFIDENCE, value_parser = clap::value_parser!(u32).range(0..=100))]
+    pub min_confidence: u32,
+}
+
+/// Undo a previous sort run.
+#[derive(Parser, Debug)]
+#[clap(about, author)]
+pub struct Undo {
+    /// Journal name, defaults to the latest run.
+    #[clap(short, long)]
+    pub journal: Option<String>,
+
+    /// List the available journals.
+    #[clap(short, long, conflicts_with = "journal")]
+    pub list: bool,
+}
+
+/// Run MediaSort as a service with a local HTTP/JSON API.
+#[derive(Parser, Debug)]
+#[clap(about, author)]
+pub struct Serve {
+    /// Address to listen on, keep it local as the API has no authentication.
+    #[clap(short, long, default_value = DEFAULT_ADDRESS)]
+    pub address: String,
+
+    /// Number of finished runs kept in memory.
+    #[clap(long, default_value_t = DEFAULT_HISTORY)]
+    pub history: usize,
+}
+
+/// Find duplicate media files in the library.
+#[derive(Parser, Debug)]
+#[clap(about, author)]
+pub struct Dedupe {
+    /// Profile whose output directory is scanned.
+    #[clap(short, long, conflicts_with = "output")]
+    pub profile: Option<String>,
+
+    /// Library directory, the output of the sorts.
+    #[clap(short, long, value_hint = ValueHint::DirPath)]
+    pub output: Option<PathBuf>,
+
+    /// Move the identical copies to the trash folder, keeping one file of each.
+    #[clap(long)]
+    pub remove: bool,
+
+    /// Where removed copies go, `.trash` in the output directory by default.
+    #[clap(long, value_hint = ValueHint::DirPath)]
+    pub trash_dir: Option<PathBuf>,
+}
+
+/// Report the missing episodes of the series in the library.
+#[derive(Parser, Debug)]
+#[clap(about, author)]
+pub struct Missing {
+    /// Series name, every series of the library by default.
+    pub series: Option<String>,
+
+    /// Profile whose output directory is scanned.
+    #[clap(short, long, conflicts_with = "output")]
+    pub profile: Option<String>,
+
+    /// Library directory, the output of the sorts.
+    #[clap(short, long, value_hint = ValueHint::DirPath)]
+    pub output: Option<PathBuf>,
+
+    /// Don't ask TVMaze, only report the gaps between episodes of the library.
+    #[clap(long)]
+    pub offline: bool,
+
+    /// Print the report as JSON.
+    #[clap(long)]
+    pub json: bool,
+
+    /// Minimum accuracy for a TVMaze show to match a series name.
+    #[clap(long, default_value_t = DEFAULT_ACCURACY_THRESHOLD)]
+    pub lookup_threshold: i64,
+
+    /// Time to live of cached show searches, in hours.
+    #[clap(long, default_value_t = DEFAULT_CACHE_TTL)]
+    pub cache_ttl: u64,
+}
+
+/// Show how file names are parsed.
+#[derive(Parser, Debug)]
+#[clap(about, author)]
+pub struct Parse {
+    /// File or release names, one per line on the standard input when there are none. Files don't need to exist.
+    pub filenames: Vec<String>,
+
+    /// Profile whose parsing rules are used.
+    #[clap(short, long, conflicts_with = "rules")]
+    pub profile: Option<String>,
+
+    /// Parsing rules file, `rules.json` in the MediaSort data folder by default.
+    #[clap(long, value_hint = ValueHint::FilePath)]
+    pub rules: Option<PathBuf>,
+
+    /// Print the result as JSON, with the release tags.
+    #[clap(long)]
+    pub json: bool,
+
+    /// Don't run ffprobe on existing files, movies are only detected from their name.
+    #[clap(long)]
+    pub no_probe: bool,
+}
+
+/// Everything MediaSort has sorted
+#[derive(Parser, Debug)]
+#[clap(about, author)]
+pub struct Library {
+    #[clap(subcommand)]
+    pub cmd: Option<LibraryCommand>,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum LibraryCommand {
+    /// List the series and movies of the library.
+    List(LibraryList),
+    /// Find files by series, title or file name.
+    Search(LibrarySearch),
+    /// Show the files of a series or movie and when they were added.
+    Show(LibraryShow),
+}
+
+/// List the series and movies of the library.
+#[derive(Clone, Parser, Debug)]
+pub struct LibraryList {}
+
+/// Find files by series, title or file name.
+#[derive(Clone, Parser, Debug)]
+pub struct LibrarySearch {
+    /// Part of the series name, episode title, original or library file name.
+    pub query: String,
+}
+
+/// Show the files of a series or movie and when they were added.
+#[derive(Clone, Parser, Debug)]
+pub struct LibraryShow {
+    /// Series or movie name.
+    pub name: String,
+}
+
+/// Metadata cache
+#[derive(Parser, Debug)]
+#[clap(about, author)]
+pub struct Cache {
+    #[clap(subcommand)]
+    pub cmd: Option<CacheCommand>,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum CacheCommand {
+    /// Delete every cached lookup.
+    Clear(Clear),
+    /// Show cache statistics.
+    Stats(Stats),
+}
+
+/// Delete every cached lookup.
+#[derive(Clone, Parser, Debug)]
+pub struct Clear {}
+
+/// Show cache statistics.
+#[derive(Clone, Parser, Debug)]
+pub struct Stats {
+    /// Time to live used to count expired entries, in hours.
+    #[clap(long, default_value_t = DEFAULT_CACHE_TTL)]
+    pub cache_ttl: u64,
 }
 
 /// Preset profiles
@@ -0,0 +1,184 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use ffprobe::ffprobe;
+use serde::{Deserialize, Serialize};
+
+use crate::release::{ReleaseTags, Source};
+
+/// What to do when the destination of a file already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Conflict {
+    /// Leave the new file in the input folder.
+    #[default]
+    Skip,
+    /// Replace the existing file, which goes to the trash folder.
+    Overwrite,
+    /// Keep both, the new file gets a ` (2)` suffix.
+    KeepBoth,
+    /// Keep the best quality file, the other one goes to the trash folder.
+    Upgrade,
+}
+
+/// Name of the trash folder in the output directory, when none is configured.
+pub const DEFAULT_TRASH_DIR: &str = ".trash";
+
+/// First free path for `path`, `Show - E01 (2).mkv`, `Show - E01 (3).mkv`...
+pub fn free_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let extension = path.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+
+    (2..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+/// Free path for `path` in `trash_dir`, keeping its path relative to the output.
+pub fn trash_path(path: &Path, output: &Path, trash_dir: &Path) -> PathBuf {
+    let relative = path
+        .strip_prefix(output)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|_| PathBuf::from(path.file_name().unwrap()));
+
+    free_path(&trash_dir.join(relative))
+}
+
+/// Video quality of a file, compared by resolution, then source, then codec, then bitrate, then size.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MediaQuality {
+    pub width: u64,
+    pub height: u64,
+    pub codec: String,
+    pub bit_rate: u64,
+    pub size: u64,
+    /// From the release name, ffprobe can't tell a BluRay from a WEB-DL.
+    #[serde(default)]
+    pub source: Option<Source>,
+}
+
+impl MediaQuality {
+    pub fn probe(path: &Path) -> Result<MediaQuality> {
+        let metadata = match ffprobe(path) {
+            Ok(metadata) => metadata,
+            Err(e) => bail!("Error while parsing file with ffprobe: {:?}", e),
+        };
+
+        let video = metadata
+            .streams
+            .iter()
+            .find(|stream| stream.codec_type.as_deref() == Some("video"));
+
+        Ok(MediaQuality {
+            width: video.and_then(|video| video.width).unwrap_or(0) as u64,
+            height: video.and_then(|video| video.height).unwrap_or(0) as u64,
+            codec: video.and_then(|video| video.codec_name.clone()).unwrap_or_default(),
+            bit_rate: metadata.format.bit_rate.and_then(|bit_rate| bit_rate.parse().ok()).unwrap_or(0),
+            size: std::fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+            source: None,
+        })
+    }
+
+    /// Completes the probe with the release tags, the resolution only when the file has no video stream ffprobe could read.
+    pub fn with_tags(mut self, tags: &ReleaseTags) -> MediaQuality {
+        self.source = tags.source;
+        if let (0, Some(resolution)) = (self.height, tags.resolution) {
+            self.height = resolution as u64;
+            self.width = self.height * 16 / 9;
+        }
+        self
+    }
+
+    /// Newer codecs give a better picture at the same bitrate.
+    fn codec_rank(&self) -> u8 {
+        match self.codec.as_str() {
+            "av1" => 3,
+            "hevc" | "h265" | "vp9" => 2,
+            "h264" | "avc" => 1,
+            _ => 0,
+        }
+    }
+
+    fn rank(&self) -> (u64, Option<u8>, u8, u64, u64) {
+        (self.width * self.height, self.source.map(|source| source.rank()), self.codec_rank(), self.bit_rate, self.size)
+    }
+
+    pub fn is_better_than(&self, other: &MediaQuality) -> bool {
+        self.rank() > other.rank()
+    }
+}
+
+impl fmt::Display for MediaQuality {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}x{} {}{} {:.1} Mb/s {:.2} GB",
+            self.width,
+            self.height,
+            self.source.map(|source| format!("{} ", source)).unwrap_or_default(),
+            if self.codec.is_empty() { "unknown" } else { &self.codec },
+            self.bit_rate as f64 / 1_000_000.0,
+            self.size as f64 / 1_000_000_000.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::release::Release;
+    use crate::scratch_dir::ScratchDir;
+
+    fn quality(height: u64, codec: &str, bit_rate: u64) -> MediaQuality {
+        MediaQuality {
+            width: height * 16 / 9,
+            height,
+            codec: codec.to_string(),
+            bit_rate,
+            size: 1_000_000_000,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn resolution_then_codec_then_bitrate() {
+        assert!(quality(1080, "h264", 4_000_000).is_better_than(&quality(720, "hevc", 8_000_000)));
+        assert!(quality(1080, "hevc", 4_000_000).is_better_than(&quality(1080, "h264", 8_000_000)));
+        assert!(quality(1080, "h264", 8_000_000).is_better_than(&quality(1080, "h264", 4_000_000)));
+        assert!(!quality(720, "h264", 4_000_000).is_better_than(&quality(720, "h264", 4_000_000)));
+    }
+
+    #[test]
+    fn release_source_ranks_after_resolution() {
+        let tagged = |height: u64, codec: &str, name: &str| quality(height, codec, 4_000_000).with_tags(&Release::parse(name).tags);
+
+        let bluray = tagged(1080, "h264", "Show.S01E01.1080p.BluRay.x264.mkv");
+        let webrip = tagged(1080, "hevc", "Show.S01E01.1080p.WEBRip.x265.mkv");
+        assert!(bluray.is_better_than(&webrip));
+        assert!(tagged(2160, "h264", "Show.S01E01.2160p.WEBRip.mkv").is_better_than(&bluray));
+        // An unknown source ranks below any known one
+        assert!(webrip.is_better_than(&tagged(1080, "av1", "Show.S01E01.mkv")));
+
+        // A file ffprobe found no video stream in still has the resolution of its name
+        let unreadable = quality(0, "", 0).with_tags(&Release::parse("Show.S01E01.720p.HDTV.mkv").tags);
+        assert_eq!((unreadable.height, unreadable.source), (720, Some(Source::Hdtv)));
+        assert!(bluray.is_better_than(&unreadable));
+    }
+
+    #[test]
+    fn free_path_adds_a_counter() {
+        let dir = ScratchDir::new("conflict");
+        let path = dir.join("Show - E01.mkv");
+        assert_eq!(free_path(&path), path);
+
+        std::fs::write(&path, b"").unwrap();
+        std::fs::write(dir.join("Show - E01 (2).mkv"), b"").unwrap();
+        assert_eq!(free_path(&path), dir.join("Show - E01 (3).mkv"));
+    }
+}
@@ -0,0 +1,416 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{bail, Context, Result};
+use indicatif::ProgressBar;
+use once_cell::sync::Lazy;
+use rayon::prelude::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::cmd::conflict::{trash_path, DEFAULT_TRASH_DIR};
+use crate::cmd::library::{library_index_path, LibraryIndex};
+use crate::cmd::parse::load_rules;
+use crate::cmd::profile::{get_or_create_data_dir, get_profile_by_name, get_profile_properties};
+use crate::cmd::sort::is_media_file;
+use crate::cmd::transfer::{hash_prefix, transfer_file, Transfer};
+use crate::cmd::undo::Journal;
+use crate::cmd::{Dedupe, Run};
+use crate::episode::Episode;
+
+/// Bytes hashed at the start and at the end of a file for its partial hash.
+const PARTIAL_HASH_SIZE: u64 = 64 * 1024;
+
+pub fn hash_index_path() -> Result<PathBuf> {
+    Ok(get_or_create_data_dir("cache")?.join("hashes.json"))
+}
+
+/// Hash of the size, the start and the end of a file, enough to tell most different files apart.
+fn partial_hash(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&size.to_le_bytes());
+
+    let mut buffer = Vec::new();
+    (&mut file).take(PARTIAL_HASH_SIZE).read_to_end(&mut buffer)?;
+    if size > PARTIAL_HASH_SIZE {
+        file.seek(SeekFrom::Start(size.saturating_sub(PARTIAL_HASH_SIZE).max(PARTIAL_HASH_SIZE)))?;
+        file.read_to_end(&mut buffer)?;
+    }
+    hasher.update(&buffer);
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+fn full_hash(path: &Path) -> Result<String> {
+    Ok(hash_prefix(File::open(path)?, u64::MAX)?.finalize().to_hex().to_string())
+}
+
+/// Size and modification time, hashes are computed again when they change.
+fn signature(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    metadata.is_file().then_some((metadata.len(), modified))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct HashEntry {
+    size: u64,
+    modified: u64,
+    partial: Option<String>,
+    full: Option<String>,
+}
+
+/// On-disk index of the library files and their hashes, filled by `dedupe` and by every sort.
+/// Hashes are computed when first needed and kept until the file changes.
+pub struct HashIndex {
+    path: PathBuf,
+    files: Mutex<HashMap<PathBuf, HashEntry>>,
+}
+
+impl HashIndex {
+    /// Loads the index file, a missing or unreadable file starts an empty index.
+    pub fn load<P: AsRef<Path>>(path: P) -> HashIndex {
+        let files = fs::read_to_string(path.as_ref())
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+
+        HashIndex {
+            path: path.as_ref().to_path_buf(),
+            files: Mutex::new(files),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let files = self.files.lock().unwrap();
+        fs::write(&self.path, serde_json::to_string(&*files)?)?;
+        Ok(())
+    }
+
+    /// Current entry of `path`, reset when the file changed and removed when it is gone.
+    fn entry(&self, path: &Path) -> Option<HashEntry> {
+        let mut files = self.files.lock().unwrap();
+
+        let Some((size, modified)) = signature(path) else {
+            files.remove(path);
+            return None;
+        };
+
+        let entry = files
+            .entry(path.to_path_buf())
+            .and_modify(|entry| {
+                if entry.size != size || entry.modified != modified {
+                    *entry = HashEntry { size, modified, partial: None, full: None };
+                }
+            })
+            .or_insert(HashEntry { size, modified, partial: None, full: None });
+
+        Some(entry.clone())
+    }
+
+    /// Hash of the whole file, computed once per version of the file.
+    pub fn hash(&self, path: &Path) -> Result<String> {
+        self.full_hash(&absolute(path))
+    }
+
+    /// Adds a library file, its hashes are computed later if ever needed.
+    pub fn add(&self, path: &Path) {
+        self.entry(&absolute(path));
+    }
+
+    fn partial_hash(&self, path: &Path) -> Result<String> {
+        let entry = self.entry(path).with_context(|| format!("File does not exist: {:?}", path))?;
+        if let Some(hash) = entry.partial {
+            return Ok(hash);
+        }
+
+        let hash = partial_hash(path)?;
+        if let Some(entry) = self.files.lock().unwrap().get_mut(path) {
+            entry.partial = Some(hash.clone());
+        }
+        Ok(hash)
+    }
+
+    fn full_hash(&self, path: &Path) -> Result<String> {
+        let entry = self.entry(path).with_context(|| format!("File does not exist: {:?}", path))?;
+        if let Some(hash) = entry.full {
+            return Ok(hash);
+        }
+
+        let hash = full_hash(path)?;
+        if let Some(entry) = self.files.lock().unwrap().get_mut(path) {
+            entry.full = Some(hash.clone());
+        }
+        Ok(hash)
+    }
+
+    /// Library file under `root` with the same content as `path`, whatever its name.
+    pub fn find_identical(&self, path: &Path, root: &Path) -> Result<Option<PathBuf>> {
+        let path = absolute(path);
+        let root = absolute(root);
+        let Some((size, _)) = signature(&path) else { return Ok(None) };
+
+        let candidates: Vec<PathBuf> = self
+            .files
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(candidate, entry)| entry.size == size && candidate.starts_with(&root) && **candidate != path)
+            .map(|(candidate, _)| candidate.clone())
+            .collect();
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+
+        // The incoming file is not part of the library, its hashes are not kept
+        let partial = partial_hash(&path)?;
+        let mut full = None;
+        for candidate in candidates {
+            if self.entry(&candidate).map(|entry| entry.size) != Some(size) || self.partial_hash(&candidate)? != partial {
+                continue;
+            }
+            if full.is_none() {
+                full = Some(full_hash(&path)?);
+            }
+            if full.as_ref() == Some(&self.full_hash(&candidate)?) {
+                return Ok(Some(candidate));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Forgets the files under `root` that are not in `files` anymore.
+    fn retain_under(&self, root: &Path, files: &[PathBuf]) {
+        let files: HashSet<&PathBuf> = files.iter().collect();
+        self.files
+            .lock()
+            .unwrap()
+            .retain(|path, _| !path.starts_with(root) || files.contains(path));
+    }
+}
+
+/// Index keys must not depend on the directory MediaSort is run from.
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Media files of the library, without the trash folder.
+pub(super) fn library_files(dir: &Path, trash_dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path == trash_dir {
+            continue;
+        }
+        if path.is_dir() {
+            library_files(&path, trash_dir, files)?;
+        } else if is_media_file(&path) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Season of the folder holding `path`, for names like `Show - E01.mkv` in `S01` or `Season 01`, `None` for other folders.
+pub(super) fn folder_season(path: &Path) -> Option<u32> {
+    static SEASON_FOLDER: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)^(?:S|Season\s*)(\d{1,2})$").unwrap());
+
+    path.parent()
+        .and_then(|parent| parent.file_name())
+        .and_then(|name| SEASON_FOLDER.captures(&name.to_string_lossy())?[1].parse().ok())
+}
+
+/// Groups of files with the same key, only the groups of two files or more.
+/// Files without a key are left out.
+fn duplicates<K, F>(files: &[PathBuf], key: F) -> Result<Vec<Vec<PathBuf>>>
+where
+    K: std::hash::Hash + Eq + Send,
+    F: Fn(&PathBuf) -> Result<Option<K>> + Sync,
+{
+    let keys: Vec<Option<K>> = files.par_iter().map(&key).collect::<Result<_>>()?;
+
+    let mut groups: HashMap<K, Vec<PathBuf>> = HashMap::new();
+    for (file, key) in files.iter().zip(keys) {
+        if let Some(key) = key {
+            groups.entry(key).or_default().push(file.clone());
+        }
+    }
+
+    let mut groups: Vec<Vec<PathBuf>> = groups.into_values().filter(|group| group.len() > 1).collect();
+    for group in groups.iter_mut() {
+        // The shortest name is kept, `Show - E01.mkv` rather than `Show - E01 (2).mkv`
+        group.sort_by_key(|path| (path.as_os_str().len(), path.clone()));
+    }
+    groups.sort();
+
+    Ok(groups)
+}
+
+fn size_of(path: &Path) -> String {
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    format!("{:.2} GB", size as f64 / 1_000_000_000.0)
+}
+
+impl Run for Dedupe {
+    fn run(&mut self) -> Result<()> {
+        let mut rules = None;
+        if let Some(name) = &self.profile {
+            let (_, output, flags) = get_profile_properties(&get_profile_by_name(name)?)?;
+            self.output = Some(PathBuf::from(output));
+            if self.trash_dir.is_none() {
+                self.trash_dir = flags.get("trash_dir").and_then(|dir| dir.as_str()).map(PathBuf::from);
+            }
+            rules = flags.get("rules").and_then(|rules| rules.as_str()).map(PathBuf::from);
+        }
+        let rules = load_rules(rules.as_deref())?;
+
+        let Some(output) = self.output.as_deref().map(absolute) else {
+            bail!("Output directory is required");
+        };
+        if !output.is_dir() {
+            bail!("Output directory does not exist: {:?}", output);
+        }
+        let trash_dir = self.trash_dir.as_deref().map(absolute).unwrap_or_else(|| output.join(DEFAULT_TRASH_DIR));
+
+        let mut files = Vec::new();
+        library_files(&output, &trash_dir, &mut files)?;
+        files.sort();
+        println!("Scanning {} media files in {:?}", files.len(), output);
+
+        let index = HashIndex::load(hash_index_path()?);
+        index.retain_under(&output, &files);
+        for file in &files {
+            index.add(file);
+        }
+
+        // Only files of the same size can be identical, the partial hash rules out most of the others
+        let identical: Vec<Vec<PathBuf>> = duplicates(&files, |file| Ok(signature(file).map(|(size, _)| size)))?
+            .into_iter()
+            .map(|group| duplicates(&group, |file| index.partial_hash(file).map(Some)))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .map(|group| duplicates(&group, |file| index.full_hash(file).map(Some)))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+        index.save()?;
+
+        let copies: Vec<PathBuf> = identical.iter().flat_map(|group| group[1..].to_vec()).collect();
+        let distinct: Vec<PathBuf> = files.iter().filter(|file| !copies.contains(file)).cloned().collect();
+
+        // Different files in the same folder parsed as the same episode, e.g. a 720p and a 1080p release
+        let same_episode = duplicates(&distinct, |file| {
+            // From the name alone, running ffprobe on the whole library is slow on a NAS
+            let episode = Episode::parse(file, &rules);
+            let season = folder_season(file).unwrap_or(episode.season);
+            Ok((!episode.is_movie && episode.episode > 0).then(|| {
+                (file.parent().map(Path::to_path_buf), season, episode.episode, episode.last_episode)
+            }))
+        })?;
+
+        if identical.is_empty() && same_episode.is_empty() {
+            println!("No duplicates found!");
+            return Ok(());
+        }
+
+        if !identical.is_empty() {
+            println!("\nIdentical files ({} copies):", copies.len());
+            for group in &identical {
+                println!("  - {} ({})", group[0].display(), size_of(&group[0]));
+                for copy in &group[1..] {
+                    println!("    copy: {}", copy.display());
+                }
+            }
+        }
+
+        if !same_episode.is_empty() {
+            println!("\nSame episode, different files:");
+            for group in &same_episode {
+                println!("  - {}", group[0].parent().unwrap().display());
+                for file in group {
+                    println!("    {} ({})", file.file_name().unwrap().to_string_lossy(), size_of(file));
+                }
+            }
+        }
+
+        if !self.remove {
+            if !copies.is_empty() {
+                println!("\nRun with --remove to move the copies to {:?}", trash_dir);
+            }
+            return Ok(());
+        }
+
+        let journal = Journal::new();
+        for copy in &copies {
+            let to = trash_path(copy, &output, &trash_dir);
+            journal.create_dir_all(to.parent().unwrap())?;
+            transfer_file(copy, &to, Transfer::Move, &journal, &ProgressBar::hidden())?;
+            println!("{} -> {}", copy.display(), to.display());
+        }
+        index.retain_under(&output, &distinct);
+        index.save()?;
+
+        let library = LibraryIndex::load(library_index_path()?);
+        for copy in &copies {
+            library.remove(copy);
+        }
+        library.save()?;
+
+        if let Some(path) = journal.save()? {
+            println!("\nRun journal saved to {:?}", path);
+        }
+        println!("Moved {} copies to the trash folder", copies.len());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch_dir::ScratchDir;
+
+    #[test]
+    fn identical_files_are_found_whatever_their_name() {
+        let dir = ScratchDir::new("dedupe");
+        let library = dir.join("library");
+        fs::create_dir_all(&library).unwrap();
+
+        let content: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let mut different = content.clone();
+        different[150_000] ^= 1;
+
+        let mut other = content.clone();
+        other[150_000] ^= 2;
+
+        fs::write(library.join("Show - E01.mkv"), &content).unwrap();
+        fs::write(library.join("Show - E02.mkv"), &different).unwrap();
+        fs::write(dir.join("Show.S01E01.1080p.mkv"), &content).unwrap();
+        fs::write(dir.join("Show.S01E02.1080p.mkv"), &other).unwrap();
+
+        let index = HashIndex::load(dir.join("hashes.json"));
+        index.add(&library.join("Show - E01.mkv"));
+        index.add(&library.join("Show - E02.mkv"));
+
+        assert_eq!(
+            index.find_identical(&dir.join("Show.S01E01.1080p.mkv"), &library).unwrap(),
+            Some(library.join("Show - E01.mkv"))
+        );
+        // Same size, start and end, only the full hash tells them apart
+        assert_eq!(index.find_identical(&dir.join("Show.S01E02.1080p.mkv"), &library).unwrap(), None);
+
+        index.save().unwrap();
+        let index = HashIndex::load(dir.join("hashes.json"));
+        assert!(index.files.lock().unwrap()[&library.join("Show - E01.mkv")].full.is_some());
+    }
+}
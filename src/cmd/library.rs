@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::cmd::conflict::MediaQuality;
+use crate::cmd::profile::get_or_create_data_dir;
+use crate::cmd::{Library, LibraryCommand, LibraryList, LibrarySearch, LibraryShow, Run};
+use crate::episode::Episode;
+
+pub fn library_index_path() -> Result<PathBuf> {
+    Ok(get_or_create_data_dir("library")?.join("index.json"))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// `2024-05-17 21:04 UTC` from a Unix timestamp.
+pub(super) fn format_date(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let (hours, minutes) = (timestamp % 86_400 / 3600, timestamp % 3600 / 60);
+
+    // Civil date from days since 1970-01-01, proleptic Gregorian calendar
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, hours, minutes)
+}
+
+/// A file placed in the library by a sort.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LibraryEntry {
+    pub series: String,
+    pub is_movie: bool,
+    pub year: Option<String>,
+    pub title: Option<String>,
+    pub season: u32,
+    pub episode: u32,
+    pub last_episode: Option<u32>,
+    /// File name before the sort, e.g. `[Group] Show - 01 [1080p].mkv`.
+    pub source: String,
+    pub destination: PathBuf,
+    pub size: u64,
+    /// BLAKE3 hash of the whole file.
+    pub hash: Option<String>,
+    pub quality: Option<MediaQuality>,
+    pub added: u64,
+}
+
+impl LibraryEntry {
+    pub fn new(episode: &Episode, destination: &Path, hash: Option<String>, quality: Option<MediaQuality>) -> LibraryEntry {
+        LibraryEntry {
+            series: episode.name.clone(),
+            is_movie: episode.is_movie,
+            year: episode.year.clone(),
+            title: episode.title.clone(),
+            season: episode.season,
+            episode: episode.episode,
+            last_episode: episode.last_episode,
+            source: episode.filename.clone(),
+            destination: destination.to_path_buf(),
+            size: fs::metadata(destination).map(|m| m.len()).unwrap_or(0),
+            hash,
+            quality,
+            added: now(),
+        }
+    }
+
+    /// `Show - S01E05-E06` or `Movie (1999)`.
+    pub fn label(&self) -> String {
+        let name = match &self.year {
+            Some(year) => format!("{} ({})", self.series, year),
+            None => self.series.clone(),
+        };
+        if self.is_movie {
+            return name;
+        }
+
+        let episode = match self.last_episode {
+            Some(last_episode) => format!("S{:02}E{:02}-E{:02}", self.season, self.episode, last_episode),
+            None => format!("S{:02}E{:02}", self.season, self.episode),
+        };
+        match &self.title {
+            Some(title) => format!("{} - {} - {}", name, episode, title),
+            None => format!("{} - {}", name, episode),
+        }
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        [
+            Some(self.series.as_str()),
+            self.title.as_deref(),
+            Some(self.source.as_str()),
+            self.destination.to_str(),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|field| field.to_lowercase().contains(&query))
+    }
+}
+
+/// Everything MediaSort has placed in a library, so it can be listed without walking the disk.
+/// Entries are keyed by destination, a file sorted again to the same place replaces its entry.
+pub struct LibraryIndex {
+    path: PathBuf,
+    entries: Mutex<HashMap<PathBuf, LibraryEntry>>,
+}
+
+impl LibraryIndex {
+    /// Loads the index file, a missing or unreadable file starts an empty index.
+    pub fn load<P: AsRef<Path>>(path: P) -> LibraryIndex {
+        let entries: Vec<LibraryEntry> = fs::read_to_string(path.as_ref())
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+
+        LibraryIndex {
+            path: path.as_ref().to_path_buf(),
+            entries: Mutex::new(entries.into_iter().map(|entry| (entry.destination.clone(), entry)).collect()),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        fs::write(&self.path, serde_json::to_string(&self.entries())?)?;
+        Ok(())
+    }
+
+    pub fn record(&self, entry: LibraryEntry) {
+        self.entries.lock().unwrap().insert(entry.destination.clone(), entry);
+    }
+
+    pub fn find(&self, destination: &Path) -> Option<LibraryEntry> {
+        self.entries.lock().unwrap().get(destination).cloned()
+    }
+
+    /// Forgets a file taken out of the library, e.g. by `undo`.
+    pub fn remove(&self, destination: &Path) {
+        self.entries.lock().unwrap().remove(destination);
+    }
+
+    /// Every entry, by series then season and episode.
+    pub fn entries(&self) -> Vec<LibraryEntry> {
+        let mut entries: Vec<LibraryEntry> = self.entries.lock().unwrap().values().cloned().collect();
+        entries.sort_by(|a, b| {
+            (a.series.to_lowercase(), a.season, a.episode, &a.destination).cmp(&(b.series.to_lowercase(), b.season, b.episode, &b.destination))
+        });
+        entries
+    }
+}
+
+impl Run for Library {
+    fn run(&mut self) -> Result<()> {
+        let cmd = self.cmd.as_mut().context("No subcommand provided")?;
+
+        cmd.run()?;
+
+        Ok(())
+    }
+}
+
+impl Run for LibraryCommand {
+    fn run(&mut self) -> Result<()> {
+        match self {
+            LibraryCommand::List(cmd) => cmd.run(),
+            LibraryCommand::Search(cmd) => cmd.run(),
+            LibraryCommand::Show(cmd) => cmd.run(),
+        }
+    }
+}
+
+impl Run for LibraryList {
+    fn run(&mut self) -> Result<()> {
+        let entries = LibraryIndex::load(library_index_path()?).entries();
+        if entries.is_empty() {
+            println!("The library is empty!");
+            return Ok(());
+        }
+
+        let mut series: BTreeMap<String, Vec<&LibraryEntry>> = BTreeMap::new();
+        let mut movies: Vec<&LibraryEntry> = Vec::new();
+        for entry in &entries {
+            if entry.is_movie {
+                movies.push(entry);
+            } else {
+                series.entry(entry.series.clone()).or_default().push(entry);
+            }
+        }
+
+        if !series.is_empty() {
+            println!("Series:");
+            for (name, episodes) in &series {
+                let mut seasons: Vec<u32> = episodes.iter().map(|entry| entry.season).collect();
+                seasons.dedup();
+                let last_added = episodes.iter().map(|entry| entry.added).max().unwrap_or(0);
+
+                println!(
+                    "  - {} ({} episodes, seasons {}, last added {})",
+                    name,
+                    episodes.len(),
+                    seasons.iter().map(|season| season.to_string()).collect::<Vec<String>>().join(", "),
+                    format_date(last_added)
+                );
+            }
+        }
+
+        if !movies.is_empty() {
+            println!("Movies:");
+            for movie in movies {
+                println!("  - {} (added {})", movie.label(), format_date(movie.added));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Run for LibrarySearch {
+    fn run(&mut self) -> Result<()> {
+        let entries: Vec<LibraryEntry> = LibraryIndex::load(library_index_path()?)
+            .entries()
+            .into_iter()
+            .filter(|entry| entry.matches(&self.query))
+            .collect();
+
+        if entries.is_empty() {
+            println!("Nothing found for {:?}", self.query);
+            return Ok(());
+        }
+
+        for entry in entries {
+            println!("{} -> {} (added {})", entry.label(), entry.destination.display(), format_date(entry.added));
+        }
+
+        Ok(())
+    }
+}
+
+impl Run for LibraryShow {
+    fn run(&mut self) -> Result<()> {
+        let entries: Vec<LibraryEntry> = LibraryIndex::load(library_index_path()?)
+            .entries()
+            .into_iter()
+            .filter(|entry| entry.series.eq_ignore_ascii_case(&self.name))
+            .collect();
+
+        if entries.is_empty() {
+            bail!("Not in the library: {}, try `library search`", self.name);
+        }
+
+        for entry in entries {
+            println!("{}", entry.label());
+            println!("  - added: {}", format_date(entry.added));
+            println!("  - file: {}", entry.destination.display());
+            println!("  - source: {}", entry.source);
+            match &entry.quality {
+                Some(quality) => println!("  - quality: {}", quality),
+                None => println!("  - size: {:.2} GB", entry.size as f64 / 1_000_000_000.0),
+            }
+            if let Some(hash) = &entry.hash {
+                println!("  - hash: {}", hash);
+            }
+            if !entry.destination.exists() {
+                println!("  - missing from the disk");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch_dir::ScratchDir;
+
+    #[test]
+    fn dates_are_formatted_in_utc() {
+        assert_eq!(format_date(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_date(951_782_400), "2000-02-29 00:00 UTC");
+        assert_eq!(format_date(1_715_979_840), "2024-05-17 21:04 UTC");
+    }
+
+    #[test]
+    fn entries_are_replaced_by_destination() {
+        let dir = ScratchDir::new("library");
+        let path = dir.join("index.json");
+        let entry = |series: &str, episode: u32, source: &str| LibraryEntry {
+            series: series.to_string(),
+            is_movie: false,
+            year: None,
+            title: None,
+            season: 1,
+            episode,
+            last_episode: None,
+            source: source.to_string(),
+            destination: PathBuf::from(format!("/media/Series/{}/S01/{} - E{:02}.mkv", series, series, episode)),
+            size: 0,
+            hash: None,
+            quality: None,
+            added: 0,
+        };
+
+        let library = LibraryIndex::load(&path);
+        library.record(entry("Show", 2, "Show.S01E02.720p.mkv"));
+        library.record(entry("Show", 1, "Show.S01E01.mkv"));
+        library.record(entry("Show", 2, "Show.S01E02.1080p.mkv"));
+        library.save().unwrap();
+
+        let entries = LibraryIndex::load(&path).entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].label(), "Show - S01E01");
+        assert_eq!(entries[1].source, "Show.S01E02.1080p.mkv");
+        assert!(entries[1].matches("1080P"));
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::cmd::cache::metadata_cache_path;
+use crate::cmd::conflict::DEFAULT_TRASH_DIR;
+use crate::cmd::dedupe::{folder_season, library_files};
+use crate::cmd::library::format_date;
+use crate::cmd::parse::load_rules;
+use crate::cmd::profile::{get_profile_by_name, get_profile_properties};
+use crate::cmd::{Missing, Run};
+use crate::episode::Episode;
+use crate::rules::Rules;
+use crate::search::cache::MetadataCache;
+use crate::search::result::SERIES;
+use crate::search::search::{ProviderChain, TVMAZE};
+use crate::search::search_tvmaze::{TvMaze, TvMazeEpisode};
+
+/// Episode numbers in the library, by season.
+type Seasons = BTreeMap<u32, BTreeSet<u32>>;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SeasonReport {
+    pub season: u32,
+    /// Episodes in the library.
+    pub have: usize,
+    /// Episodes aired so far, unknown offline.
+    pub aired: Option<usize>,
+    pub missing: Vec<u32>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct UpcomingEpisode {
+    pub season: u32,
+    pub episode: u32,
+    pub name: Option<String>,
+    /// `None` until the episode is scheduled.
+    pub airdate: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeriesReport {
+    pub series: String,
+    /// Show matched on TVMaze, `None` offline or without a match.
+    pub tvmaze: Option<String>,
+    pub seasons: Vec<SeasonReport>,
+    pub upcoming: Vec<UpcomingEpisode>,
+    /// Why only the gaps between episodes of the library are reported.
+    pub error: Option<String>,
+}
+
+/// `2024-05-17`, the format of TVMaze air dates.
+fn today() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    format_date(now)[..10].to_string()
+}
+
+/// Episodes missing between E01 and the last episode of each season in the library, specials are skipped.
+fn internal_gaps(seasons: &Seasons) -> Vec<SeasonReport> {
+    seasons
+        .iter()
+        .filter(|(season, _)| **season > 0)
+        .map(|(season, episodes)| SeasonReport {
+            season: *season,
+            have: episodes.len(),
+            aired: None,
+            missing: (1..=episodes.last().copied().unwrap_or(0)).filter(|episode| !episodes.contains(episode)).collect(),
+        })
+        .collect()
+}
+
+/// Aired episodes missing from the library, by season, and the episodes still to come.
+fn aired_gaps(seasons: &Seasons, episodes: &[TvMazeEpisode], today: &str) -> (Vec<SeasonReport>, Vec<UpcomingEpisode>) {
+    let mut aired: Seasons = BTreeMap::new();
+    let mut upcoming = Vec::new();
+
+    for episode in episodes {
+        let Some(number) = episode.number.filter(|_| episode.season > 0) else { continue };
+        let airdate = episode.airdate.clone().filter(|airdate| !airdate.is_empty());
+
+        match &airdate {
+            Some(airdate) if airdate.as_str() <= today => {
+                aired.entry(episode.season).or_default().insert(number);
+            }
+            _ => {
+                if !seasons.get(&episode.season).is_some_and(|have| have.contains(&number)) {
+                    upcoming.push(UpcomingEpisode {
+                        season: episode.season,
+                        episode: number,
+                        name: episode.name.clone(),
+                        airdate,
+                    });
+                }
+            }
+        }
+    }
+    upcoming.sort_by_key(|episode| (episode.season, episode.episode));
+
+    let numbers: BTreeSet<u32> = aired.keys().chain(seasons.keys()).copied().filter(|season| *season > 0).collect();
+    let reports = numbers
+        .into_iter()
+        .map(|season| {
+            let have = seasons.get(&season).cloned().unwrap_or_default();
+            let aired = aired.get(&season).cloned().unwrap_or_default();
+            SeasonReport {
+                season,
+                have: have.len(),
+                aired: Some(aired.len()),
+                missing: aired.difference(&have).copied().collect(),
+            }
+        })
+        .collect();
+
+    (reports, upcoming)
+}
+
+/// Series episodes of the library by parsed series name.
+fn library_series(output: &Path, trash_dir: &Path, rules: &Rules) -> Result<BTreeMap<String, Seasons>> {
+    let mut files = Vec::new();
+    library_files(output, trash_dir, &mut files)?;
+
+    let mut series: BTreeMap<String, Seasons> = BTreeMap::new();
+    for file in files {
+        // The name is enough, probing every file of a large library would take minutes
+        let episode = Episode::parse(&file, rules);
+        if episode.is_movie || episode.episode == 0 {
+            continue;
+        }
+
+        // `Show - E01.mkv` only has its season in the folder name, it parses as an absolute number
+        let season = folder_season(&file).unwrap_or(episode.season);
+        let last_episode = episode.last_episode.unwrap_or(episode.episode);
+        series
+            .entry(episode.name.clone())
+            .or_default()
+            .entry(season)
+            .or_default()
+            .extend(episode.episode..=last_episode);
+    }
+
+    Ok(series)
+}
+
+impl Missing {
+    fn report(&self, name: &str, seasons: &Seasons, chain: Option<&ProviderChain>, today: &str) -> SeriesReport {
+        let mut report = SeriesReport {
+            series: name.to_string(),
+            tvmaze: None,
+            seasons: Vec::new(),
+            upcoming: Vec::new(),
+            error: None,
+        };
+
+        let Some(chain) = chain else {
+            report.seasons = internal_gaps(seasons);
+            return report;
+        };
+
+        let episodes = chain.lookup(name, "", SERIES.clone(), self.lookup_threshold).and_then(|found| match found {
+            Some((_, show)) => {
+                report.tvmaze = Some(show.string());
+                TvMaze::new().episodes(&show.id)
+            }
+            None => bail!("No match found on TVMaze"),
+        });
+
+        match episodes {
+            Ok(episodes) => (report.seasons, report.upcoming) = aired_gaps(seasons, &episodes, today),
+            Err(e) => {
+                report.seasons = internal_gaps(seasons);
+                report.error = Some(format!("{:#}", e));
+            }
+        }
+
+        report
+    }
+}
+
+fn print_report(report: &SeriesReport) {
+    match &report.tvmaze {
+        Some(show) if *show != report.series => println!("{} (TVMaze: {})", report.series, show),
+        _ => println!("{}", report.series),
+    }
+    if let Some(error) = &report.error {
+        println!("  - {}, only gaps between episodes are reported", error);
+    }
+
+    for season in &report.seasons {
+        let count = match season.aired {
+            Some(aired) => format!("{}/{} episodes", season.have, aired),
+            None => format!("{} episodes", season.have),
+        };
+        if season.missing.is_empty() {
+            println!("  - S{:02}: {}", season.season, count);
+        } else {
+            let missing: Vec<String> = season.missing.iter().map(|episode| format!("E{:02}", episode)).collect();
+            println!("  - S{:02}: {}, missing {}", season.season, count, missing.join(", "));
+        }
+    }
+
+    for episode in &report.upcoming {
+        let name = episode.name.as_deref().map(|name| format!(" {}", name)).unwrap_or_default();
+        let airdate = episode.airdate.as_deref().unwrap_or("date to be announced");
+        println!("  - upcoming: S{:02}E{:02}{} ({})", episode.season, episode.episode, name, airdate);
+    }
+}
+
+impl Run for Missing {
+    fn run(&mut self) -> Result<()> {
+        let mut rules = None;
+        if let Some(name) = &self.profile {
+            let (_, output, flags) = get_profile_properties(&get_profile_by_name(name)?)?;
+            self.output = Some(PathBuf::from(output));
+            self.offline |= flags.get("offline").and_then(|offline| offline.as_bool()).unwrap_or(false);
+            rules = flags.get("rules").and_then(|rules| rules.as_str()).map(PathBuf::from);
+        }
+        let rules = load_rules(rules.as_deref())?;
+
+        let Some(output) = self.output.clone() else {
+            bail!("Output directory is required");
+        };
+        if !output.is_dir() {
+            bail!("Output directory does not exist: {:?}", output);
+        }
+
+        let mut series = library_series(&output, &output.join(DEFAULT_TRASH_DIR), &rules)?;
+        if let Some(name) = &self.series {
+            series.retain(|series, _| series.eq_ignore_ascii_case(name));
+            if series.is_empty() {
+                bail!("Series not found in the library: {}", name);
+            }
+        }
+
+        let chain = match self.offline {
+            true => None,
+            false => {
+                let cache = Arc::new(MetadataCache::load(metadata_cache_path()?, self.cache_ttl));
+                Some(ProviderChain::from_names(&[TVMAZE.to_string()], None)?.with_cache(cache, false))
+            }
+        };
+
+        let today = today();
+        let reports: Vec<SeriesReport> = series
+            .iter()
+            .map(|(name, seasons)| self.report(name, seasons, chain.as_ref(), &today))
+            .collect();
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&reports)?);
+            return Ok(());
+        }
+
+        for report in &reports {
+            print_report(report);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::scratch_dir::ScratchDir;
+
+    fn seasons(episodes: &[(u32, u32)]) -> Seasons {
+        let mut seasons = Seasons::new();
+        for (season, episode) in episodes {
+            seasons.entry(*season).or_default().insert(*episode);
+        }
+        seasons
+    }
+
+    fn tvmaze(season: u32, number: u32, airdate: Option<&str>) -> TvMazeEpisode {
+        TvMazeEpisode {
+            season,
+            number: Some(number),
+            name: None,
+            airdate: airdate.map(|airdate| airdate.to_string()),
+        }
+    }
+
+    #[test]
+    fn offline_only_reports_internal_gaps() {
+        let reports = internal_gaps(&seasons(&[(0, 3), (1, 1), (1, 2), (1, 4), (2, 1)]));
+
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0], SeasonReport { season: 1, have: 3, aired: None, missing: vec![3] });
+        assert!(reports[1].missing.is_empty());
+    }
+
+    #[test]
+    fn library_seasons_come_from_the_season_folders() {
+        let dir = ScratchDir::new("missing");
+        let files = [
+            "Series/Show/S01/Show - E01-E03.mkv",
+            "Series/Show/S02/Show - E01.mkv",
+            "Series/Show/S02/Show - E04.mkv",
+            "Series/Show/S00/Show - E01.mkv",
+            "Other.Show.S03E02.mkv",
+        ];
+        for file in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"media").unwrap();
+        }
+
+        let series = library_series(&dir, &dir.join(DEFAULT_TRASH_DIR), &Rules::default()).unwrap();
+        assert_eq!(series["Show"], seasons(&[(0, 1), (1, 1), (1, 2), (1, 3), (2, 1), (2, 4)]));
+        assert_eq!(series["Other Show"], seasons(&[(3, 2)]));
+
+        let reports = internal_gaps(&series["Show"]);
+        assert_eq!(reports[1], SeasonReport { season: 2, have: 2, aired: None, missing: vec![2, 3] });
+    }
+
+    #[test]
+    fn aired_episodes_are_missing_and_the_others_upcoming() {
+        let episodes = vec![
+            tvmaze(1, 1, Some("2024-01-01")),
+            tvmaze(1, 2, Some("2024-01-08")),
+            tvmaze(1, 3, Some("2024-01-15")),
+            tvmaze(2, 1, Some("2024-06-01")),
+            tvmaze(2, 2, Some("2024-06-08")),
+            tvmaze(2, 3, None),
+        ];
+
+        let (reports, upcoming) = aired_gaps(&seasons(&[(1, 1), (1, 3)]), &episodes, "2024-06-01");
+
+        assert_eq!(reports[0], SeasonReport { season: 1, have: 2, aired: Some(3), missing: vec![2] });
+        assert_eq!(reports[1], SeasonReport { season: 2, have: 0, aired: Some(1), missing: vec![1] });
+        assert_eq!(upcoming.iter().map(|episode| episode.episode).collect::<Vec<u32>>(), vec![2, 3]);
+        assert_eq!(upcoming[1].airdate, None);
+    }
+}
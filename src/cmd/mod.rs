@@ -1,6 +1,16 @@
+mod cache;
 mod cmd;
+mod conflict;
+mod dedupe;
+mod library;
+mod missing;
+mod parse;
 mod profile;
+mod serve;
 mod sort;
+mod transfer;
+mod undo;
+mod watch;
 
 use anyhow::Result;
 
@@ -15,6 +25,13 @@ impl Run for Cmd {
         match self {
             Cmd::Sort(cmd) => cmd.run(),
             Cmd::Profile(cmd) => cmd.run(),
+            Cmd::Undo(cmd) => cmd.run(),
+            Cmd::Cache(cmd) => cmd.run(),
+            Cmd::Serve(cmd) => cmd.run(),
+            Cmd::Dedupe(cmd) => cmd.run(),
+            Cmd::Library(cmd) => cmd.run(),
+            Cmd::Missing(cmd) => cmd.run(),
+            Cmd::Parse(cmd) => cmd.run(),
         }
     }
 }
@@ -0,0 +1,181 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::cmd::profile::{get_or_create_data_dir, get_profile_by_name, get_profile_properties};
+use crate::cmd::{Parse, Run};
+use crate::episode::Episode;
+use crate::release::ReleaseTags;
+use crate::rules::Rules;
+
+pub fn rules_path() -> Result<PathBuf> {
+    Ok(get_or_create_data_dir("rules")?.join("rules.json"))
+}
+
+/// Parses file names with the rules of `path`, or of the default rules file when there is one.
+pub(super) fn load_rules(path: Option<&Path>) -> Result<Rules> {
+    match path {
+        Some(path) if !path.exists() => bail!("Rules file does not exist: {:?}", path),
+        Some(path) => Rules::load(path),
+        None => Rules::load(rules_path()?),
+    }
+}
+
+/// What MediaSort makes of a file name.
+#[derive(Debug, Serialize)]
+struct ParsedName {
+    filename: String,
+    filename_clean: String,
+    name: String,
+    year: Option<String>,
+    title: Option<String>,
+    season: u32,
+    episode: u32,
+    last_episode: Option<u32>,
+    absolute_episode: Option<u32>,
+    is_movie: bool,
+    /// From 0 to 100, files under `sort --min-confidence` go to `Unsorted/`.
+    movie_confidence: u32,
+    movie_reasons: Vec<String>,
+    /// User rule the name matched, `None` for the built-in parser.
+    rule: Option<String>,
+    tags: ReleaseTags,
+}
+
+/// Parses `name`, only probing it when it is an existing file and `probe` is set.
+fn parse_name(name: &str, rules: &Rules, probe: bool) -> Result<ParsedName> {
+    let path = Path::new(name);
+    if path.file_name().is_none() {
+        bail!("Not a file name: {:?}", name);
+    }
+
+    let mut episode = Episode::parse(path, rules);
+    if probe && path.is_file() {
+        episode.classify(episode.movie_score(true));
+    }
+
+    Ok(ParsedName {
+        filename: episode.filename,
+        filename_clean: episode.filename_clean,
+        name: episode.name,
+        year: episode.year,
+        title: episode.title,
+        season: episode.season,
+        episode: episode.episode,
+        last_episode: episode.last_episode,
+        absolute_episode: episode.absolute_episode,
+        is_movie: episode.is_movie,
+        movie_confidence: episode.movie.confidence(),
+        movie_reasons: episode.movie.reasons,
+        rule: episode.rule,
+        tags: episode.tags,
+    })
+}
+
+fn print_table(parsed: &[ParsedName]) {
+    let mut rows = vec![["FILE", "CLEAN", "NAME", "SEASON", "EPISODE", "MOVIE", "CONFIDENCE", "REASONS", "RULE"].map(String::from)];
+    for name in parsed {
+        let episode = match name.last_episode {
+            Some(last_episode) => format!("{}-{}", name.episode, last_episode),
+            None => name.episode.to_string(),
+        };
+        rows.push([
+            name.filename.clone(),
+            name.filename_clean.clone(),
+            name.name.clone(),
+            name.season.to_string(),
+            episode,
+            if name.is_movie { "yes" } else { "no" }.to_string(),
+            name.movie_confidence.to_string(),
+            name.movie_reasons.join(", "),
+            name.rule.clone().unwrap_or_else(|| "-".to_string()),
+        ]);
+    }
+
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].chars().count()).max().unwrap_or(0))
+        .collect();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+impl Run for Parse {
+    fn run(&mut self) -> Result<()> {
+        if let Some(name) = &self.profile {
+            let (_, _, flags) = get_profile_properties(&get_profile_by_name(name)?)?;
+            self.rules = flags.get("rules").and_then(|rules| rules.as_str()).map(PathBuf::from);
+        }
+        let rules = load_rules(self.rules.as_deref())?;
+
+        if self.filenames.is_empty() {
+            for line in io::stdin().lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    self.filenames.push(line.trim().to_string());
+                }
+            }
+        }
+        if self.filenames.is_empty() {
+            bail!("No file name to parse");
+        }
+
+        let parsed = self
+            .filenames
+            .iter()
+            .map(|name| parse_name(name, &rules, !self.no_probe))
+            .collect::<Result<Vec<ParsedName>>>()?;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&parsed)?);
+        } else {
+            print_table(&parsed);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch_dir::ScratchDir;
+
+    #[test]
+    fn names_are_parsed_without_the_file() {
+        let rules = Rules::default();
+
+        let parsed = parse_name("/nowhere/Show.S01E02E03.Pilot.720p.mkv", &rules, true).unwrap();
+        assert_eq!((parsed.name.as_str(), parsed.season, parsed.episode, parsed.last_episode), ("Show", 1, 2, Some(3)));
+        assert!(!parsed.is_movie);
+        assert_eq!(parsed.movie_reasons, vec!["-60 season and episode numbers"]);
+
+        let parsed = parse_name("Inception.2010.1080p.BluRay.x264", &rules, true).unwrap();
+        assert!(parsed.is_movie);
+        assert_eq!(parsed.movie_confidence, 65);
+        assert_eq!(parsed.year.as_deref(), Some("2010"));
+
+        assert!(parse_name("..", &rules, true).is_err());
+    }
+
+    #[test]
+    fn names_are_parsed_with_the_selected_rules() {
+        let dir = ScratchDir::new("rules");
+        let path = dir.join("rules.json");
+        assert!(load_rules(Some(&path)).is_err());
+
+        std::fs::write(&path, r#"{"patterns": [{"name": "erai", "regex": "^\\[Erai-raws\\] (?P<series>.+) - (?P<episode>\\d+)"}]}"#).unwrap();
+        let rules = load_rules(Some(&path)).unwrap();
+
+        let parsed = parse_name("[Erai-raws] Show Title - 03 [1080p].mkv", &rules, false).unwrap();
+        assert_eq!((parsed.rule.as_deref(), parsed.name.as_str(), parsed.episode), (Some("erai"), "Show Title", 3));
+        assert_eq!(parse_name("Show.S01E01.mkv", &rules, false).unwrap().rule, None);
+    }
+}
@@ -11,19 +11,23 @@ use serde_json::{json, Deserializer, Serializer, Value};
 
 use crate::cmd::{Create, Delete, List, Profile, ProfileCommand, Run, Edit};
 
-fn get_or_create_profiles_dir() -> Result<PathBuf> {
+pub fn get_or_create_data_dir(name: &str) -> Result<PathBuf> {
     let base_dirs = BaseDirs::new().context("Could not get base directories")?;
 
-    let profiles_dir = base_dirs
+    let data_dir = base_dirs
         .data_local_dir()
         .join("MediaSort")
-        .join("profiles");
+        .join(name);
 
-    if !profiles_dir.try_exists()? {
-        fs::create_dir_all(&profiles_dir).context("Could not create profiles directory")?;
+    if !data_dir.try_exists()? {
+        fs::create_dir_all(&data_dir).with_context(|| format!("Could not create {} directory", name))?;
     }
 
-    Ok(profiles_dir)
+    Ok(data_dir)
+}
+
+fn get_or_create_profiles_dir() -> Result<PathBuf> {
+    get_or_create_data_dir("profiles")
 }
 
 pub fn get_profile_by_name(name: &str) -> Result<PathBuf> {
@@ -38,6 +42,28 @@ pub fn get_profile_by_name(name: &str) -> Result<PathBuf> {
     Ok(profile_path)
 }
 
+/// Profile handling a torrent client category: the one listing it in its `categories` flag,
+/// e.g. `categories=tv,anime`, or else the profile named after it.
+pub fn get_profile_by_category(category: &str) -> Result<Option<PathBuf>> {
+    let profiles_dir = get_or_create_profiles_dir()?;
+
+    for entry in fs::read_dir(&profiles_dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("pms") {
+            continue;
+        }
+
+        let (_, _, flags) = get_profile_properties(&path)?;
+        let categories = flags.get("categories").and_then(|categories| categories.as_str()).unwrap_or_default();
+        if categories.split(',').any(|name| name.trim().eq_ignore_ascii_case(category)) {
+            return Ok(Some(path));
+        }
+    }
+
+    let profile_path = profiles_dir.join(format!("{}.pms", category));
+    Ok(profile_path.exists().then_some(profile_path))
+}
+
 pub fn get_profile_properties(path: &PathBuf) -> Result<(String,String, serde_json::Map<String, Value>)> {
     let profile_str = fs::read_to_string(path)?;
 
@@ -54,9 +80,10 @@ fn get_default_flags() -> serde_json::Map<String, serde_json::Value> {
     let mut flags = serde_json::Map::new();
     flags.insert("verbose".to_string(), serde_json::Value::Bool(false));
     flags.insert("recursive".to_string(), serde_json::Value::Bool(false));
-    let num_cpus: usize = num_cpus::get() / 2;
+    let num_cpus: usize = (num_cpus::get() / 2).max(1);
     flags.insert("threads".to_string(), serde_json::Value::Number(serde_json::Number::from(num_cpus)));
     flags.insert("webhook".to_string(), serde_json::Value::String("".to_string()));
+    flags.insert("lookup".to_string(), serde_json::Value::Bool(false));
     flags
 }
 
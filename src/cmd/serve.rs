@@ -0,0 +1,369 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Error, Result};
+use clap::Parser;
+use reqwest::Url;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::cmd::profile::{get_profile_by_name, get_profile_properties};
+use crate::cmd::undo::{read_journal, recent_moves, JournalEntry};
+use crate::cmd::{Run, Serve, Sort};
+
+/// Local only, the API has no authentication.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+/// Number of finished runs kept in memory.
+pub const DEFAULT_HISTORY: usize = 20;
+/// Number of moves returned by `GET /moves` without a `limit`.
+const DEFAULT_MOVES_LIMIT: usize = 50;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// A sort requested through the API.
+#[derive(Clone, Debug, Serialize)]
+pub struct RunReport {
+    pub id: u64,
+    pub profile: String,
+    pub status: RunStatus,
+    pub queued: u64,
+    pub started: Option<u64>,
+    pub finished: Option<u64>,
+    pub error: Option<String>,
+    pub moves: Vec<JournalEntry>,
+}
+
+/// Runs sorts for the service, swapped in tests.
+pub trait Runner: Send + Sync {
+    /// Fails if the profile does not exist.
+    fn check(&self, profile: &str) -> Result<()>;
+
+    /// Sorts the profile, returns the moves that were made.
+    fn sort(&self, profile: &str) -> Result<Vec<JournalEntry>>;
+
+    /// Media files waiting in the profile input directory.
+    fn pending(&self, profile: &str) -> Result<Vec<PathBuf>>;
+}
+
+/// Runs the profiles saved by `profile create`, exactly like `sort --profile`.
+pub struct ProfileRunner;
+
+impl ProfileRunner {
+    fn profile_sort(profile: &str) -> Result<Sort> {
+        Ok(Sort::try_parse_from(["sort", "--profile", profile])?)
+    }
+}
+
+impl Runner for ProfileRunner {
+    fn check(&self, profile: &str) -> Result<()> {
+        get_profile_by_name(profile)?;
+        Ok(())
+    }
+
+    fn sort(&self, profile: &str) -> Result<Vec<JournalEntry>> {
+        let mut sort = Self::profile_sort(profile)?;
+        sort.run()?;
+
+        match &sort.journal {
+            Some(journal) => read_journal(journal),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn pending(&self, profile: &str) -> Result<Vec<PathBuf>> {
+        let sort = Self::profile_sort(profile)?;
+        let (input, _, _) = get_profile_properties(&get_profile_by_name(profile)?)?;
+
+        let mut pending: Vec<PathBuf> = fs::read_dir(input)?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.is_file() && sort.is_media(path))
+            .collect();
+        pending.sort();
+
+        Ok(pending)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    queued: VecDeque<RunReport>,
+    running: Option<RunReport>,
+    /// Finished runs, newest first.
+    history: VecDeque<RunReport>,
+}
+
+impl State {
+    fn find(&self, id: u64) -> Option<&RunReport> {
+        self.running
+            .iter()
+            .chain(self.queued.iter())
+            .chain(self.history.iter())
+            .find(|run| run.id == id)
+    }
+}
+
+/// Queue of sort requests, processed one at a time by a worker thread.
+#[derive(Clone)]
+pub struct Service {
+    runner: Arc<dyn Runner>,
+    state: Arc<Mutex<State>>,
+    sender: Sender<()>,
+    history: usize,
+}
+
+impl Service {
+    pub fn start(runner: Arc<dyn Runner>, history: usize) -> Service {
+        let (sender, receiver) = channel();
+        let service = Service {
+            runner,
+            state: Arc::new(Mutex::new(State::default())),
+            sender,
+            history,
+        };
+
+        let worker = service.clone();
+        thread::spawn(move || worker.work(receiver));
+
+        service
+    }
+
+    fn work(&self, receiver: Receiver<()>) {
+        while receiver.recv().is_ok() {
+            // Under one lock, so the run is always either queued or running
+            let mut run = {
+                let mut state = self.state.lock().unwrap();
+                let Some(mut run) = state.queued.pop_front() else { continue };
+                run.status = RunStatus::Running;
+                run.started = Some(now());
+                state.running = Some(run.clone());
+                run
+            };
+
+            match self.runner.sort(&run.profile) {
+                Ok(moves) => {
+                    run.status = RunStatus::Succeeded;
+                    run.moves = moves;
+                }
+                Err(e) => {
+                    println!("Sort of profile {:?} failed: {:#}", run.profile, e);
+                    run.status = RunStatus::Failed;
+                    run.error = Some(format!("{:#}", e));
+                }
+            }
+            run.finished = Some(now());
+
+            let mut state = self.state.lock().unwrap();
+            state.running = None;
+            state.history.push_front(run);
+            state.history.truncate(self.history);
+        }
+    }
+
+    fn enqueue(&self, profile: &str) -> RunReport {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+
+        let run = RunReport {
+            id: state.next_id,
+            profile: profile.to_string(),
+            status: RunStatus::Queued,
+            queued: now(),
+            started: None,
+            finished: None,
+            error: None,
+            moves: Vec::new(),
+        };
+        state.queued.push_back(run.clone());
+        _ = self.sender.send(());
+
+        run
+    }
+
+    /// Answers one API call, `url` is the path and query of the request.
+    pub fn handle(&self, method: &Method, url: &str) -> (u16, Value) {
+        let Ok(url) = Url::parse(&format!("http://localhost{}", url)) else {
+            return (400, json!({ "error": "Invalid URL" }));
+        };
+        let segments: Vec<&str> = url.path_segments().map(|s| s.filter(|s| !s.is_empty()).collect()).unwrap_or_default();
+        let query = |key: &str| url.query_pairs().find(|(k, _)| k == key).map(|(_, v)| v.into_owned());
+
+        let result = match (method, segments.as_slice()) {
+            (Method::Get, ["status"]) => Ok(self.status()),
+            (Method::Get, ["runs"]) => Ok(json!(self.state.lock().unwrap().history)),
+            (Method::Get, ["runs", id]) => match id.parse().ok().and_then(|id| self.state.lock().unwrap().find(id).cloned()) {
+                Some(run) => Ok(json!(run)),
+                None => return (404, json!({ "error": format!("Run not found: {}", id) })),
+            },
+            (Method::Post, ["sort", profile]) => match self.runner.check(profile) {
+                Ok(()) => return (202, json!(self.enqueue(profile))),
+                Err(e) => return (404, json!({ "error": format!("{:#}", e) })),
+            },
+            (Method::Get, ["files"]) => match query("profile") {
+                Some(profile) => self.files(&profile),
+                None => return (400, json!({ "error": "Missing profile parameter" })),
+            },
+            (Method::Get, ["moves"]) => {
+                let limit = query("limit").and_then(|limit| limit.parse().ok()).unwrap_or(DEFAULT_MOVES_LIMIT);
+                recent_moves(limit).map(|moves| json!(moves))
+            }
+            (_, ["status"] | ["runs"] | ["runs", _] | ["sort", _] | ["files"] | ["moves"]) => {
+                return (405, json!({ "error": format!("Method {} not allowed", method) }))
+            }
+            _ => return (404, json!({ "error": format!("Unknown endpoint: {}", url.path()) })),
+        };
+
+        match result {
+            Ok(body) => (200, body),
+            Err(e) => (500, json!({ "error": format!("{:#}", e) })),
+        }
+    }
+
+    fn status(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        json!({
+            "running": state.running,
+            "queued": state.queued,
+            "last": state.history.front(),
+        })
+    }
+
+    /// Files waiting in the profile input and the ones moved by its last run.
+    fn files(&self, profile: &str) -> Result<Value, Error> {
+        let pending = self.runner.pending(profile)?;
+        let processed: Vec<JournalEntry> = self
+            .state
+            .lock()
+            .unwrap()
+            .history
+            .iter()
+            .find(|run| run.profile == profile)
+            .map(|run| run.moves.clone())
+            .unwrap_or_default();
+
+        Ok(json!({ "pending": pending, "processed": processed }))
+    }
+
+    /// Serves the API until the server is closed.
+    pub fn serve(&self, server: &Server) {
+        for request in server.incoming_requests() {
+            self.respond(request);
+        }
+    }
+
+    fn respond(&self, request: Request) {
+        let (status, body) = self.handle(request.method(), request.url());
+        let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+        let response = Response::from_string(body.to_string()).with_status_code(status).with_header(header);
+
+        if let Err(e) = request.respond(response) {
+            println!("Could not answer request: {:#}", e);
+        }
+    }
+}
+
+impl Run for Serve {
+    fn run(&mut self) -> Result<()> {
+        let server = Server::http(&self.address).map_err(|e| Error::msg(format!("Could not listen on {}: {}", self.address, e)))?;
+        let service = Service::start(Arc::new(ProfileRunner), self.history);
+
+        println!("MediaSort API listening on http://{}", server.server_addr());
+        service.serve(&server);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::undo::MoveMethod;
+    use anyhow::bail;
+    use std::time::Duration;
+
+    struct FakeRunner;
+
+    impl Runner for FakeRunner {
+        fn check(&self, profile: &str) -> Result<()> {
+            if profile != "anime" {
+                bail!("Profile not found: {}", profile);
+            }
+            Ok(())
+        }
+
+        fn sort(&self, _profile: &str) -> Result<Vec<JournalEntry>> {
+            Ok(vec![JournalEntry {
+                source: PathBuf::from("/downloads/Show.S01E01.mkv"),
+                destination: PathBuf::from("/media/Series/Show/S01/Show - E01.mkv"),
+                method: MoveMethod::Rename,
+                timestamp: 0,
+            }])
+        }
+
+        fn pending(&self, _profile: &str) -> Result<Vec<PathBuf>> {
+            Ok(vec![PathBuf::from("/downloads/Show.S01E02.mkv")])
+        }
+    }
+
+    #[test]
+    fn api_queues_and_reports_runs() {
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let url = format!("http://{}", server.server_addr());
+        let service = Service::start(Arc::new(FakeRunner), DEFAULT_HISTORY);
+        {
+            let server = server.clone();
+            thread::spawn(move || service.serve(&server));
+        }
+        let client = reqwest::blocking::Client::new();
+
+        let response = client.post(format!("{}/sort/anime", url)).send().unwrap();
+        assert_eq!(response.status().as_u16(), 202);
+        let run: Value = response.json().unwrap();
+        assert_eq!(run["id"], 1);
+
+        let mut run = Value::Null;
+        for _ in 0..50 {
+            run = client.get(format!("{}/runs/1", url)).send().unwrap().json().unwrap();
+            if run["status"] == "succeeded" {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(run["status"], "succeeded");
+        assert_eq!(run["moves"][0]["destination"], "/media/Series/Show/S01/Show - E01.mkv");
+
+        let status: Value = client.get(format!("{}/status", url)).send().unwrap().json().unwrap();
+        assert_eq!(status["last"]["id"], 1);
+        assert!(status["running"].is_null());
+
+        let files: Value = client.get(format!("{}/files?profile=anime", url)).send().unwrap().json().unwrap();
+        assert_eq!(files["pending"][0], "/downloads/Show.S01E02.mkv");
+        assert_eq!(files["processed"][0]["source"], "/downloads/Show.S01E01.mkv");
+
+        assert_eq!(client.post(format!("{}/sort/movies", url)).send().unwrap().status().as_u16(), 404);
+        assert_eq!(client.get(format!("{}/sort/anime", url)).send().unwrap().status().as_u16(), 405);
+        assert_eq!(client.get(format!("{}/nothing", url)).send().unwrap().status().as_u16(), 404);
+
+        server.unblock();
+    }
+}
@@ -1,43 +1,117 @@
 use core::num;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::hash::Hash;
 use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use anyhow::{bail, Ok, Result};
+use clap::ValueEnum;
 
 use indicatif::{ProgressBar,ProgressStyle, MultiProgress};
 use once_cell::sync::Lazy;
-use rayon::{prelude::*, ThreadPoolBuilder};
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
 
-use serde_json::json;
+use serde_json::{json, Value};
 
-use crate::cmd::profile::get_profile_by_name;
+use crate::cmd::conflict::{free_path, trash_path, Conflict, MediaQuality, DEFAULT_TRASH_DIR};
+use crate::cmd::dedupe::{hash_index_path, HashIndex};
+use crate::cmd::library::{library_index_path, LibraryEntry, LibraryIndex};
+use crate::cmd::parse::load_rules;
+use crate::cmd::profile::{get_profile_by_category, get_profile_by_name};
+use crate::cmd::transfer::{safe_copy, transfer_file, Transfer};
 use crate::cmd::{profile, Run, Sort};
+use crate::cmd::undo::{Journal, MoveMethod};
 use crate::episode::Episode;
-
-static MULTI_PROGRESS: Lazy<MultiProgress> = Lazy::new(|| MultiProgress::new());
+use crate::naming::{Layout, Template};
+use crate::release::{Release, ReleaseTags};
+use crate::cmd::cache::metadata_cache_path;
+use crate::search::cache::{MetadataCache, DEFAULT_CACHE_TTL};
+use crate::search::result::{SeasonDetails, MOVIE, SERIES};
+use crate::search::search::{absolute_to_season, default_provider_names, ProviderChain, DEFAULT_ACCURACY_THRESHOLD};
+use crate::search::search_tmdb::Tmdb;
+
+/// Files sorted as a movie or an episode with less confidence go to [`UNSORTED_DIR`].
+pub const DEFAULT_MIN_CONFIDENCE: u32 = 15;
+/// Folder of the output directory for the files that could not be told apart as a movie or an episode.
+pub const UNSORTED_DIR: &str = "Unsorted";
+/// Below this confidence, `--lookup` also asks the providers whether the name is a series or a movie.
+const LOOKUP_CONFIDENCE: u32 = 60;
+
+pub(crate) static MULTI_PROGRESS: Lazy<MultiProgress> = Lazy::new(MultiProgress::new);
 
 impl Run for Sort {
     fn run(&mut self) -> Result<()> {
-        if self.profile.is_some() {
+        let mut profile: Option<PathBuf> = match &self.profile {
+            Some(name) => Some(get_profile_by_name(name)?),
+            None => None,
+        };
+        if let Some(category) = &self.category {
+            profile = get_profile_by_category(category)?;
+            match &profile {
+                Some(path) => println!("Category {:?} uses profile {:?}", category, path.file_stem().unwrap()),
+                None => println!("No profile for category {:?}, using the command line options", category),
+            }
+        }
+
+        if let Some(profile) = profile {
 
-            let profile = get_profile_by_name(self.profile.as_ref().unwrap())?;
             let (input, output, flags) = profile::get_profile_properties(&profile)?;
             self.input = Some(PathBuf::from(input));
             println!("input: {:?}", self.input.clone().unwrap());
             self.output = Some(PathBuf::from(output));
             println!("output: {:?}", self.output.clone().unwrap());
 
-            self.verbose = flags["verbose"].as_bool().unwrap_or(false);
-            self.threads = flags["threads"].as_u64().map(|n| n as usize);
-            self.recursive = flags["recursive"].as_bool().unwrap_or(false);
-            self.webhook = flags["webhook"].as_str().map(|s| s.to_string());
+            self.verbose = flags.get("verbose").and_then(Value::as_bool).unwrap_or(false);
+            self.threads = flags.get("threads").and_then(Value::as_u64).map(|n| n as usize);
+            self.recursive = flags.get("recursive").and_then(Value::as_bool).unwrap_or(false);
+            self.webhook = flags.get("webhook").and_then(Value::as_str).map(|s| s.to_string());
+            self.lookup = flags.get("lookup").and_then(Value::as_bool).unwrap_or(false);
+            self.lookup_threshold = flags.get("lookup_threshold").and_then(Value::as_i64).unwrap_or(DEFAULT_ACCURACY_THRESHOLD);
+            self.providers = flags
+                .get("providers")
+                .and_then(Value::as_str)
+                .map(|providers| providers.split(',').map(|name| name.trim().to_string()).collect());
+            self.offline = flags.get("offline").and_then(Value::as_bool).unwrap_or(false);
+            self.cache_ttl = flags.get("cache_ttl").and_then(Value::as_u64).unwrap_or(DEFAULT_CACHE_TTL);
+            if let Some(api_key) = flags.get("tmdb_api_key").and_then(Value::as_str) {
+                self.tmdb_api_key = Some(api_key.to_string());
+            }
+            if let Some(api_url) = flags.get("tmdb_api_url").and_then(Value::as_str) {
+                self.tmdb_api_url = Some(api_url.to_string());
+            }
+            self.layout = match flags.get("layout").and_then(Value::as_str) {
+                Some(layout) => match Layout::from_str(layout, true) {
+                    Result::Ok(layout) => Some(layout),
+                    Err(_) => bail!("Invalid layout in profile: {}", layout),
+                },
+                None => None,
+            };
+            self.transfer = match flags.get("transfer").and_then(Value::as_str) {
+                Some(transfer) => match Transfer::from_str(transfer, true) {
+                    Result::Ok(transfer) => Some(transfer),
+                    Err(_) => bail!("Invalid transfer in profile: {}", transfer),
+                },
+                None => self.transfer,
+            };
+            self.conflict = match flags.get("conflict").and_then(Value::as_str) {
+                Some(conflict) => match Conflict::from_str(conflict, true) {
+                    Result::Ok(conflict) => Some(conflict),
+                    Err(_) => bail!("Invalid conflict policy in profile: {}", conflict),
+                },
+                None => None,
+            };
+            self.trash_dir = flags.get("trash_dir").and_then(Value::as_str).map(PathBuf::from);
+            self.series_template = flags.get("series_template").and_then(Value::as_str).map(|s| s.to_string());
+            self.movie_template = flags.get("movie_template").and_then(Value::as_str).map(|s| s.to_string());
+            self.rules = flags.get("rules").and_then(Value::as_str).map(PathBuf::from);
+            self.min_confidence = flags.get("min_confidence").and_then(Value::as_u64).map(|n| n.min(100) as u32).unwrap_or(DEFAULT_MIN_CONFIDENCE);
         }
+        self.parse_rules = load_rules(self.rules.as_deref())?;
 
-        if self.input.is_none() {
+        if self.input.is_none() && self.single.is_none() {
             bail!("Input directory is required");
         }
 
@@ -45,10 +119,22 @@ impl Run for Sort {
             bail!("Output directory is required");
         }
 
+        // Fail before anything is moved
+        self.templates()?;
+
+        if self.watch {
+            return self.watch();
+        }
+
         let global_timer = Instant::now();
 
         self.sort_medias_threaded()?;
 
+        if self.dry_run {
+            println!("\nDry run completed in {:?}, nothing was moved", global_timer.elapsed());
+            return Ok(());
+        }
+
         println!(
             "\nMedias sorted successfully in {:?}",
             global_timer.elapsed()
@@ -59,14 +145,14 @@ impl Run for Sort {
 }
 
 impl Sort {
-    fn verbose(&self, message: &str) {
+    pub(super) fn verbose(&self, message: &str) {
         if self.verbose {
             println!("{}", message);
         }
     }
 
-    fn visit_dirs(&self, dir: &PathBuf, cb: &dyn Fn(&PathBuf) -> Result<()>) -> Result<()> {
-        let paths: fs::ReadDir = fs::read_dir(dir.clone()).unwrap();
+    pub(super) fn visit_dirs(&self, dir: &Path, cb: &dyn Fn(&Path) -> Result<()>) -> Result<()> {
+        let paths: fs::ReadDir = fs::read_dir(dir).unwrap();
 
         for path in paths {
             let path: PathBuf = path.unwrap().path();
@@ -80,9 +166,9 @@ impl Sort {
         Ok(())
     }
 
-    fn register_media(&self, path: &PathBuf, episodes: &mut Vec<Episode>, start_instant: &Instant) -> Result<()> {
+    fn register_media(&self, path: &Path, episodes: &mut Vec<Episode>, start_instant: &Instant) -> Result<()> {
         if self.is_media(path) {
-            let episode: Episode = Episode::new(path);
+            let episode: Episode = Episode::new(path, &self.parse_rules);
             episodes.push(episode.clone());
 
             self.verbose(&format!(
@@ -95,13 +181,27 @@ impl Sort {
     }
 
 
-    fn get_medias_from_input(&self) -> Result<Vec<Episode>> {
+    /// Folder of a special found below the input and where it goes, the whole folder is moved to the series folder.
+    /// Movies, numbered episodes and files directly in the input are sorted one by one.
+    fn specials_folder(&self, path: &Path, episode: &Episode, series_template: &Template) -> Result<Option<(PathBuf, PathBuf)>> {
+        let folder = path.parent().unwrap();
+        if episode.is_movie || episode.season > 0 || Some(folder) == self.input.as_deref() {
+            return Ok(None);
+        }
+
+        let to = self.output.as_ref().unwrap().join(series_template.series_dir(episode)?);
+        Ok(Some((folder.to_path_buf(), to)))
+    }
+
+    fn get_medias_from_input(&self, journal: &Journal) -> Result<Vec<Episode>> {
         let timer = Instant::now();
 
         let input_path = self.input.clone();
         let paths: fs::ReadDir = fs::read_dir(input_path.unwrap()).unwrap();
         let episodes: Mutex<Vec<Episode>> = Vec::new().into();
         let has_media = Mutex::new(false);
+        let planned_folders: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+        let (series_template, _) = self.templates()?;
         for path in paths {
             let start_instant: Instant = Instant::now();
             let path: PathBuf = path.unwrap().path();
@@ -118,29 +218,37 @@ impl Sort {
                     //move the file to the source directory
 
                     if path.is_file() && self.is_media(path) {
-                        let episode: Episode = Episode::new(path);
-                        if episode.season == 0 {
-
-                            let series_folder: PathBuf = path.parent().unwrap().to_path_buf().parent().unwrap().to_path_buf();
-                            let to = self.output.clone().unwrap().join("Series").join(&episode.name);
-
-                            if is_on_same_drive(&series_folder, &to.clone()) {
+                        let episode: Episode = Episode::new(path, &self.parse_rules);
+                        if let Some((series_folder, to)) = self.specials_folder(path, &episode, &series_template)? {
+                            if self.dry_run {
+                                if planned_folders.lock().unwrap().insert(series_folder.clone()) {
+                                    print_plan(&series_folder, &to);
+                                }
+                            }
+                            else if is_on_same_drive(&series_folder, self.output.clone().unwrap()) {
                                 move_by_rename_recursive(&series_folder, &to)?;
+                                journal.record_move(&series_folder, &to, MoveMethod::Rename);
                             }
                             else {
                                 move_by_copy_recursive(&series_folder, &to)?;
+                                journal.record_move(&series_folder, &to, MoveMethod::Copy);
                             }
 
                         }
+                        else if self.dry_run {
+                            //the plan keeps the file where it is, the real run moves it to the source directory first
+                            self.register_media(path, &mut episodes_guard, &register_timer).unwrap();
+                        }
                         else {
                             //we move the file to the source directory
                             let to = self.input.clone().unwrap().join(&episode.filename);
-                            fs::rename(&path, to.clone()).unwrap();
+                            fs::rename(path, to.clone()).unwrap();
+                            journal.record_move(path, &to, MoveMethod::Rename);
                             self.register_media(&to, &mut episodes_guard, &register_timer).unwrap();
                         }
                     }
                     Ok(())
-                }).unwrap();
+                })?;
 
 
             }
@@ -164,24 +272,55 @@ impl Sort {
         Ok(episodes)
     }
 
-    fn is_media(&self, path: &PathBuf) -> bool {
-        static MEDIA_EXTENSIONS: Lazy<HashSet<&str>> = Lazy::new(|| {
-            ["mp4", "mkv", "avi", "mov", "flv", "wmv", "webm"]
-                .iter()
-                .cloned()
-                .collect()
-        });
-
-        path.extension()
-            .and_then(|ext| ext.to_str())
-            .map(|ext_str| MEDIA_EXTENSIONS.contains(&ext_str))
-            .unwrap_or(false)
+    /// Media files of a single download, a file or a folder, without touching the rest of the input.
+    fn get_single_medias(&self, path: &PathBuf) -> Result<Vec<Episode>> {
+        let episodes: Mutex<Vec<Episode>> = Vec::new().into();
+        let timer = Instant::now();
+
+        if path.is_dir() {
+            self.visit_dirs(path, &|path| self.register_media(path, &mut episodes.lock().unwrap(), &timer))?;
+        } else if path.is_file() {
+            self.register_media(path, &mut episodes.lock().unwrap(), &timer)?;
+        } else {
+            bail!("File does not exist: {:?}", path);
+        }
+
+        let mut episodes = episodes.into_inner().unwrap();
+        if episodes.is_empty() {
+            bail!("No media files found in {:?}", path);
+        }
+
+        if let Some(torrent_name) = &self.torrent_name {
+            for episode in episodes.iter_mut() {
+                episode.apply_release_name(torrent_name, &self.parse_rules);
+            }
+        }
+
+        Ok(episodes)
+    }
+
+    pub(super) fn is_media(&self, path: &Path) -> bool {
+        is_media_file(path)
     }
 
-    fn sort_medias_threaded(&self) -> Result<()> {
-        self.verbose(&format!("Sorting medias in {:?}", self.input.clone().unwrap()));
+    fn sort_medias_threaded(&mut self) -> Result<()> {
+        self.verbose(&format!("Sorting medias in {:?}", self.single.as_ref().or(self.input.as_ref()).unwrap()));
+
+        let pool = self.thread_pool()?;
+        let journal = Journal::new();
+        let sorted = pool.install(|| self.sort_medias(&journal));
+
+        self.journal = journal.save()?;
+        if let Some(path) = &self.journal {
+            println!("Run journal saved to {:?}", path);
+        }
+
+        sorted
+    }
 
-        let max_cpu_count: usize = num_cpus::get() - 1;
+    /// Thread pool of the run, local so several runs can happen in the same process.
+    pub(super) fn thread_pool(&self) -> Result<ThreadPool> {
+        let max_cpu_count: usize = (num_cpus::get() - 1).max(1);
         let mut num_threads: usize = self.threads.unwrap_or(max_cpu_count);
 
         if num_threads > max_cpu_count {
@@ -197,97 +336,351 @@ impl Sort {
             bail!("Number of threads must be greater than 0");
         }
 
-        // Configure global thread pool
-        ThreadPoolBuilder::new()
-            .num_threads(num_threads)
-            .build_global()
-            .unwrap();
-        let mut episodes: Vec<Episode> = self.get_medias_from_input()?;
+        Ok(ThreadPoolBuilder::new().num_threads(num_threads).build()?)
+    }
+
+    fn sort_medias(&self, journal: &Journal) -> Result<()> {
+        let episodes: Vec<Episode> = match &self.single {
+            Some(path) => self.get_single_medias(path)?,
+            None => self.get_medias_from_input(journal)?,
+        };
+
+        self.sort_episodes(episodes, journal)
+    }
 
+    pub(super) fn sort_episodes(&self, episodes: Vec<Episode>, journal: &Journal) -> Result<()> {
         if episodes.is_empty() {
             return Ok(());
         }
+
+        let index = HashIndex::load(hash_index_path()?);
+        // Hashing every file would make the preview as slow as the run, a dry run doesn't look for library copies
+        let mut episodes = match self.dry_run {
+            true => episodes,
+            false => self.skip_library_copies(episodes, &index)?,
+        };
+        if episodes.is_empty() {
+            index.save()?;
+            return Ok(());
+        }
+
+        if self.lookup {
+            self.lookup_names(&mut episodes)?;
+        }
+
+        let templates = self.templates()?;
         let dir_set: Arc<Mutex<HashSet<PathBuf>>> = Arc::new(Mutex::new(HashSet::new()));
+        let library = LibraryIndex::load(library_index_path()?);
+
+        if self.dry_run {
+            // Sequential so the plan is printed in a stable order
+            episodes.sort_by(|a, b| a.full_path.cmp(&b.full_path));
+            for episode in &episodes {
+                let to_path: PathBuf = self.destination(episode, &templates)?;
+                self.find_or_create_dir(&to_path, dir_set.clone(), journal)?;
+                self.move_media(episode, &to_path, journal, &library, &ProgressBar::hidden())?;
+            }
+            return Ok(());
+        }
 
+        // Counted in bytes, a single large copy must not look frozen
+        let total_bytes: u64 = episodes
+            .iter()
+            .map(|episode| fs::metadata(&episode.full_path).map(|m| m.len()).unwrap_or(0))
+            .sum();
+        let total_files = episodes.len();
+        let moved_files = AtomicUsize::new(0);
 
         MULTI_PROGRESS.set_draw_target(indicatif::ProgressDrawTarget::stderr());
-        let pb = MULTI_PROGRESS.add(indicatif::ProgressBar::new(episodes.len() as u64));
+        let pb = MULTI_PROGRESS.add(indicatif::ProgressBar::new(total_bytes));
         pb.set_style(
             ProgressStyle::default_bar()
             .progress_chars("#>-")
-            .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {msg} {pos}/{len}")?
+            .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {msg} {bytes}/{total_bytes} ({binary_bytes_per_sec}, ETA {eta})")?
         );
-        pb.set_message("Moving files");
+        pb.set_message(format!("Moving files 0/{}", total_files));
         pb.enable_steady_tick(std::time::Duration::from_millis(100));
         episodes.par_iter_mut().try_for_each(|episode| {
-            let dest_dir: PathBuf = self.find_or_create_dir(&episode, dir_set.clone()).unwrap();
-            self.move_media(&episode, &dest_dir)?;
-            pb.inc(1);
+            let to_path: PathBuf = self.destination(episode, &templates)?;
+            self.find_or_create_dir(&to_path, dir_set.clone(), journal)?;
+            if let Some(to_path) = self.move_media(episode, &to_path, journal, &library, &pb)? {
+                self.record_in_library(episode, &to_path, &index, &library);
+            }
+            let moved = moved_files.fetch_add(1, Ordering::Relaxed) + 1;
+            pb.set_message(format!("Moving files {}/{}", moved, total_files));
             Ok(())
         })?;
 
         pb.finish_with_message("Moving completed");
+        index.save()?;
+        library.save()?;
 
         Ok(())
     }
 
-    fn find_or_create_dir(
-        &self,
-        episode: &Episode,
-        dir_set: Arc<Mutex<HashSet<PathBuf>>>,
-    ) -> Result<PathBuf> {
+    /// Remembers where a file was placed, a hash or ffprobe failure only leaves its field empty.
+    fn record_in_library(&self, episode: &Episode, to_path: &Path, index: &HashIndex, library: &LibraryIndex) {
+        let to_path = std::path::absolute(to_path).unwrap_or_else(|_| to_path.to_path_buf());
+        let hash = index.hash(&to_path).ok();
+        let quality = MediaQuality::probe(&to_path).ok().map(|quality| quality.with_tags(&episode.tags));
+
+        library.record(LibraryEntry::new(episode, &to_path, hash, quality));
+    }
+
+    /// Drops the files identical to one already in the library, whatever their name.
+    fn skip_library_copies(&self, episodes: Vec<Episode>, index: &HashIndex) -> Result<Vec<Episode>> {
+        let output = self.output.as_ref().unwrap();
+        let mut kept = Vec::new();
+
+        for episode in episodes {
+            match index.find_identical(&episode.full_path, output)? {
+                Some(existing) => println!("{:?} is already in the library as {:?}, skipping", episode.full_path, existing),
+                None => kept.push(episode),
+            }
+        }
+
+        Ok(kept)
+    }
+
+    /// Series and movie templates, from the options or the layout.
+    fn templates(&self) -> Result<(Template, Template)> {
+        let (series, movies) = self.layout.unwrap_or_default().templates();
+        let series = Template::parse(self.series_template.as_deref().unwrap_or(series))?;
+        let movies = Template::parse(self.movie_template.as_deref().unwrap_or(movies))?;
+        Ok((series, movies))
+    }
+
+    fn destination(&self, episode: &Episode, (series, movies): &(Template, Template)) -> Result<PathBuf> {
         if episode.name == "unknow" {
             bail!("Episode name is unknow");
         }
 
-        let mut dest_dir: PathBuf =
-            PathBuf::from(&<Option<PathBuf> as Clone>::clone(&self.output).unwrap());
+        if episode.movie.confidence() < self.min_confidence {
+            self.verbose(&format!(
+                "Unsure whether {:?} is a movie ({}), moving it to {}",
+                episode.filename,
+                episode.movie.reasons.join(", "),
+                UNSORTED_DIR
+            ));
+            return Ok(self.output.as_ref().unwrap().join(UNSORTED_DIR).join(&episode.filename));
+        }
 
-        if episode.is_movie {
-            dest_dir.push("Films");
-        } else {
-            dest_dir.push("Series");
+        let template = if episode.is_movie { movies } else { series };
+        Ok(self.output.as_ref().unwrap().join(template.render(episode)?))
+    }
+
+    fn provider_chain(&self, cache: Arc<MetadataCache>) -> Result<ProviderChain> {
+        let tmdb: Option<Tmdb> = self
+            .tmdb_api_key
+            .clone()
+            .filter(|api_key| !api_key.is_empty())
+            .map(|api_key| Tmdb::new(self.tmdb_api_url.clone(), api_key));
+
+        let names = self
+            .providers
+            .clone()
+            .unwrap_or_else(|| default_provider_names(tmdb.is_some()));
+
+        Ok(ProviderChain::from_names(&names, tmdb)?.with_cache(cache, self.offline))
+    }
+
+    /// Searches names the release name and runtime could not classify both as a series and a movie.
+    fn lookup_kinds(&self, chain: &ProviderChain, episodes: &mut [Episode]) {
+        let names: HashSet<String> = episodes
+            .iter()
+            .filter(|episode| episode.movie.confidence() < LOOKUP_CONFIDENCE)
+            .map(|episode| episode.name.clone())
+            .collect();
+
+        let mut matches: HashMap<String, (Option<i64>, Option<i64>)> = HashMap::new();
+        for name in names {
+            let accuracy = |media_type| match chain.lookup(&name, "", media_type, self.lookup_threshold) {
+                Result::Ok(found) => found.map(|(_, result)| result.accuracy),
+                Err(e) => {
+                    self.verbose(&format!("Lookup failed for {:?}: {:#}", name, e));
+                    None
+                }
+            };
+            matches.insert(name.clone(), (accuracy(SERIES.clone()), accuracy(MOVIE.clone())));
+        }
+
+        for episode in episodes.iter_mut() {
+            if let Some((series, movie)) = matches.get(&episode.name) {
+                let mut score = episode.movie.clone();
+                score.add_lookup(*series, *movie);
+                self.verbose(&format!("{:?} is {} after lookup", episode.filename, if score.is_movie() { "a movie" } else { "an episode" }));
+                episode.classify(score);
+            }
         }
+    }
 
-        {
-            let mut dir_set_guard = dir_set.lock().unwrap();
+    /// Replaces parsed names by their canonical title and year, one query per distinct name.
+    fn lookup_names(&self, episodes: &mut [Episode]) -> Result<()> {
+        let cache = Arc::new(MetadataCache::load(metadata_cache_path()?, self.cache_ttl));
+        let chain = self.provider_chain(cache.clone())?;
+        self.lookup_kinds(&chain, episodes);
+
+        let names: HashSet<(String, bool)> = episodes
+            .iter()
+            .map(|episode| (episode.name.clone(), episode.is_movie))
+            .collect();
+        let absolute_names: HashSet<String> = episodes
+            .iter()
+            .filter(|episode| episode.absolute_episode.is_some())
+            .map(|episode| episode.name.clone())
+            .collect();
+
+        let mut canonical_names: HashMap<(String, bool), (String, String)> = HashMap::new();
+        let mut seasons: HashMap<String, Vec<SeasonDetails>> = HashMap::new();
+        for (name, is_movie) in names {
+            let media_type = if is_movie { MOVIE.clone() } else { SERIES.clone() };
+
+            match chain.lookup(&name, "", media_type.clone(), self.lookup_threshold) {
+                Result::Ok(Some((provider, result))) => {
+                    self.verbose(&format!("Resolved {:?} as {:?} on {}", name, result.string(), provider.name()));
+
+                    // Season episode counts are only needed to place absolute episode numbers
+                    if !is_movie && absolute_names.contains(&name) {
+                        match provider.details(&result.id, media_type) {
+                            Result::Ok(details) => {
+                                seasons.insert(name.clone(), details.seasons);
+                            }
+                            Err(e) => {
+                                println!("Could not fetch the seasons of {:?}, keeping absolute numbering: {:#}", name, e);
+                            }
+                        }
+                    }
 
-            if !dir_set_guard.contains(&dest_dir) {
-                fs::create_dir_all(&dest_dir).expect("Failed to create directory");
-                dir_set_guard.insert(dest_dir.clone());
+                    canonical_names.insert((name, is_movie), (result.title, result.year));
+                }
+                Result::Ok(None) => {
+                    self.verbose(&format!("No match found for {:?}, keeping the parsed name", name));
+                }
+                Err(e) => {
+                    println!("Lookup failed for {:?}, keeping the parsed name: {:#}", name, e);
+                }
             }
         }
 
-        if !episode.is_movie {
-            let serie_dir = PathBuf::from(dest_dir.clone()).join(&episode.name);
-            if dir_set.lock().unwrap().contains(&serie_dir) {
-                return Ok(serie_dir);
+        for episode in episodes.iter_mut() {
+            if let (Some(absolute_episode), Some(seasons)) = (episode.absolute_episode, seasons.get(&episode.name)) {
+                if let Some((season, number)) = absolute_to_season(absolute_episode, seasons) {
+                    self.verbose(&format!(
+                        "Mapped {:?} episode {} to S{:02}E{:02}",
+                        episode.name, absolute_episode, season, number
+                    ));
+                    episode.season = season;
+                    episode.episode = number;
+                }
+            }
+
+            if let Some((title, year)) = canonical_names.get(&(episode.name.clone(), episode.is_movie)) {
+                episode.name = title.clone();
+                episode.year = Some(year.clone()).filter(|year| !year.is_empty()).or(episode.year.take());
             }
+        }
 
-            let season_dir: PathBuf =
-                PathBuf::from(serie_dir.clone()).join(format!("S{:02}", &episode.season));
+        if !self.offline {
+            cache.save()?;
+        }
+
+        Ok(())
+    }
+
+    /// Release tags of the file at `path`, from its name before the sort when the library has it.
+    fn existing_tags(&self, path: &Path, library: &LibraryIndex) -> ReleaseTags {
+        let destination = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let name = match library.find(&destination) {
+            Some(entry) => entry.source,
+            None => path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        };
+
+        Release::parse(&name).tags
+    }
 
-            if dir_set.lock().unwrap().contains(&season_dir) {
-                return Ok(season_dir);
+    /// Applies the conflict policy when `to` already exists, returns where `episode` should go, if anywhere.
+    fn resolve_conflict(&self, episode: &Episode, to: &Path, journal: &Journal, library: &LibraryIndex) -> Result<Option<PathBuf>> {
+        let from = episode.full_path.as_path();
+        match self.conflict.unwrap_or_default() {
+            Conflict::Skip => Ok(None),
+            Conflict::KeepBoth => Ok(Some(free_path(to))),
+            Conflict::Overwrite => {
+                self.trash(to, journal)?;
+                Ok(Some(to.to_path_buf()))
             }
+            Conflict::Upgrade => {
+                let incoming = MediaQuality::probe(from)?.with_tags(&episode.tags);
+                let existing = MediaQuality::probe(to)?.with_tags(&self.existing_tags(to, library));
+
+                if incoming.is_better_than(&existing) {
+                    println!("Upgrading {:?}: {} -> {}", to, existing, incoming);
+                    self.trash(to, journal)?;
+                    return Ok(Some(to.to_path_buf()));
+                }
+
+                println!("Keeping {:?} ({}), {:?} is not better ({})", to, existing, from, incoming);
+                // A source kept for seeding stays where it is
+                if self.transfer.unwrap_or_default() == Transfer::Move {
+                    self.trash(from, journal)?;
+                }
+                Ok(None)
+            }
+        }
+    }
 
-            fs::create_dir_all(&season_dir).expect("Failed to create directory");
-            dir_set.lock().unwrap().insert(season_dir.clone());
+    /// Moves a replaced file to the trash folder, keeping its path relative to the output.
+    fn trash(&self, path: &Path, journal: &Journal) -> Result<()> {
+        let output = self.output.as_ref().unwrap();
+        let trash_dir = self.trash_dir.clone().unwrap_or_else(|| output.join(DEFAULT_TRASH_DIR));
+        let trash_path = trash_path(path, output, &trash_dir);
 
-            return Ok(season_dir);
+        if self.dry_run {
+            print_plan(path, &trash_path);
+            return Ok(());
+        }
+
+        journal.create_dir_all(trash_path.parent().unwrap())?;
+        transfer_file(path, &trash_path, Transfer::Move, journal, &ProgressBar::hidden())?;
+        self.verbose(&format!("Moved {:?} to the trash {:?}", path, trash_path));
+
+        Ok(())
+    }
+
+    /// Creates the parent folders of `to_path`, once per folder.
+    fn find_or_create_dir(
+        &self,
+        to_path: &Path,
+        dir_set: Arc<Mutex<HashSet<PathBuf>>>,
+        journal: &Journal,
+    ) -> Result<PathBuf> {
+        let dest_dir: PathBuf = to_path.parent().unwrap().to_path_buf();
+
+        let mut dir_set_guard = dir_set.lock().unwrap();
+        if !dir_set_guard.contains(&dest_dir) {
+            if !self.dry_run {
+                journal.create_dir_all(&dest_dir)?;
+            }
+            dir_set_guard.insert(dest_dir.clone());
         }
 
         Ok(dest_dir)
     }
 
-    fn move_media(&self, episode: &Episode, dest_dir: &PathBuf) -> Result<()> {
+    /// Moves one episode to `to_path`, `progress` counts the bytes transferred.
+    /// Returns where the file went, `None` when it was left in place.
+    fn move_media(
+        &self,
+        episode: &Episode,
+        to_path: &Path,
+        journal: &Journal,
+        library: &LibraryIndex,
+        progress: &ProgressBar,
+    ) -> Result<Option<PathBuf>> {
         let timer = Instant::now();
-        let from_dir: PathBuf = <Option<PathBuf> as Clone>::clone(&self.input)
-            .unwrap()
-            .clone();
-        let to_dir: PathBuf = dest_dir.clone();
+        let from_path: PathBuf = episode.full_path.clone();
+        let from_dir: PathBuf = from_path.parent().unwrap().to_path_buf();
+        let to_dir: PathBuf = to_path.parent().unwrap().to_path_buf();
 
-        let from_path: PathBuf = from_dir.join(&episode.filename);
         if !from_path.exists() {
             bail!("File does not exist: {:?}", from_path);
         } else if !from_path.is_file() {
@@ -301,25 +694,7 @@ impl Sort {
             to_dir
         ));
 
-        let new_filename: String;
-
-        if episode.is_movie {
-            new_filename = format!("{}.{}", episode.name, episode.extension);
-        } else {
-            if episode.episode >= 100 {
-                new_filename = format!(
-                    "{} - E{:03}.{}",
-                    episode.name, episode.episode, episode.extension
-                );
-            } else {
-                new_filename = format!(
-                    "{} - E{:02}.{}",
-                    episode.name, episode.episode, episode.extension
-                );
-            }
-        }
-
-        let to_path: PathBuf = to_dir.join(new_filename.clone());
+        let mut to_path: PathBuf = to_path.to_path_buf();
         if from_dir == to_dir {
             bail!("Source and destination directories are the same");
         } else if to_path.exists() {
@@ -327,16 +702,19 @@ impl Sort {
                 "File already exists: {:?} in {:?}",
                 to_path, timer.elapsed()
             ));
-            //if already exists, skip
-            return Ok(());
+            match self.resolve_conflict(episode, &to_path, journal, library)? {
+                Some(path) => to_path = path,
+                None => return Ok(None),
+            }
         }
 
-        if is_on_same_drive(&from_path.clone(), &to_path.clone()) {
-            move_by_rename(&from_path, &to_path)?;
-        } else {
-            move_by_copy(&from_path, &to_path)?;
+        if self.dry_run {
+            print_plan(&from_path, &to_path);
+            return Ok(None);
         }
 
+        transfer_file(&from_path, &to_path, self.transfer.unwrap_or_default(), journal, progress)?;
+
         self.verbose(&format!(
             "Moved {:?} to {:?} in {:?}",
             episode.filename_clean,
@@ -344,24 +722,35 @@ impl Sort {
             timer.elapsed()
         ));
 
-        if self.webhook.is_some() && !self.webhook.as_ref().unwrap().is_empty() && self.webhook.as_ref().unwrap() != "" {
+        if let Some(webhook) = self.webhook.as_ref().filter(|webhook| !webhook.is_empty()) {
             println!("Sending webhook");
             let mut message = format!(
-                "Added: `{} - S{:02}E{:02}` to the library",
-                episode.name, episode.season, episode.episode
+                "Added: `{} - S{:02}{}` to the library",
+                episode.name, episode.season, episode.episode_tag()
             );
             if episode.is_movie {
                 message = format!("Added: `{}` to the library", episode.name);
             }
 
+            let tags = [
+                episode.tags.resolution.map(|resolution| format!("{}p", resolution)),
+                episode.tags.source.map(|source| source.to_string()),
+                episode.tags.edition.clone(),
+            ];
+            let tags: Vec<String> = tags.into_iter().flatten().chain(episode.tags.languages.clone()).collect();
+            if !tags.is_empty() {
+                message = format!("{} ({})", message, tags.join(" "));
+            }
+
             let payload = json!({
                 "content": message,
+                "tags": episode.tags,
             });
 
             let client = reqwest::blocking::Client::new();
 
             let res = client
-                .post(self.webhook.as_ref().unwrap())
+                .post(webhook)
                 .json(&payload)
                 .send()?;
 
@@ -372,12 +761,30 @@ impl Sort {
             self.verbose(&format!("Sent webhook: {:?}", payload));
         }
 
-        Ok(())
+        Ok(Some(to_path))
     }
 }
 
+pub fn is_media_file(path: &Path) -> bool {
+    static MEDIA_EXTENSIONS: Lazy<HashSet<&str>> = Lazy::new(|| {
+        ["mp4", "mkv", "avi", "mov", "flv", "wmv", "webm"]
+            .iter()
+            .cloned()
+            .collect()
+    });
+
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext_str| MEDIA_EXTENSIONS.contains(&ext_str))
+        .unwrap_or(false)
+}
+
+fn print_plan(from: &Path, to: &Path) {
+    println!("{} -> {}", from.display(), to.display());
+}
+
 #[cfg(target_os = "windows")]
-fn is_on_same_drive<P: AsRef<Path>>(path1: P, path2: P) -> bool {
+pub fn is_on_same_drive<P: AsRef<Path>, Q: AsRef<Path>>(path1: P, path2: Q) -> bool {
     let path1 = path1.as_ref();
     let path2 = path2.as_ref();
 
@@ -388,8 +795,8 @@ fn is_on_same_drive<P: AsRef<Path>>(path1: P, path2: P) -> bool {
 }
 
 #[cfg(target_os = "linux")]
-fn is_on_same_drive<P: AsRef<Path>, Q: AsRef<Path>>(path1: P, path2: Q) -> bool {
-    use std::fs;
+pub fn is_on_same_drive<P: AsRef<Path>, Q: AsRef<Path>>(path1: P, path2: Q) -> bool {
+    use std::os::unix::fs::MetadataExt;
 
     let fs1 = fs::metadata(path1).expect("Unable to read metadata").dev();
     let fs2 = fs::metadata(path2).expect("Unable to read metadata").dev();
@@ -397,26 +804,13 @@ fn is_on_same_drive<P: AsRef<Path>, Q: AsRef<Path>>(path1: P, path2: Q) -> bool
     fs1 == fs2
 }
 
-fn move_by_rename<P: AsRef<Path>>(from: P, to: P) -> Result<()> {
-
-    let pb = MULTI_PROGRESS.add(indicatif::ProgressBar::new(1));
-    pb.set_style(
-        indicatif::ProgressStyle::default_bar()
-        .progress_chars("#>-")
-        .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {msg}")?
-    );
-
-    pb.set_message(format!("{}", from.as_ref().file_name().unwrap().to_str().unwrap()));
-
+pub fn move_by_rename<P: AsRef<Path>>(from: P, to: P) -> Result<()> {
     fs::rename(from.as_ref(), to)?;
-
-    pb.finish_and_clear();
-
     Ok(())
 }
 
-fn move_by_copy<P: AsRef<Path> + Send + Sync>(from: P, to: P ) -> Result<()> {
-    fs::copy(from.as_ref(), to)?;
+pub fn move_by_copy<P: AsRef<Path> + Send + Sync>(from: P, to: P ) -> Result<()> {
+    safe_copy(from.as_ref(), to.as_ref())?;
     fs::remove_file(from)?;
     Ok(())
 }
@@ -487,7 +881,7 @@ pub fn move_by_copy_recursive<U: AsRef<Path>, V: AsRef<Path>>(from: U, to: V) ->
                     Some(filename) => {
                         let dest_path = dest.join(filename);
 
-                        fs::copy(&path, &dest_path)?;
+                        safe_copy(&path, &dest_path)?;
                         pb.inc(1);
                     }
                     None => {
@@ -569,3 +963,87 @@ pub fn move_by_rename_recursive<U: AsRef<Path>, V: AsRef<Path>>(from: U, to: V)
     pb.finish_and_clear();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::rules::Rules;
+    use crate::scratch_dir::ScratchDir;
+
+    #[test]
+    fn dry_run_moves_nothing() {
+        let dir = ScratchDir::new("dry-run");
+        fs::create_dir_all(dir.join("input/Show.S01")).unwrap();
+        let files = [dir.join("input/Show.S01E01.mkv"), dir.join("input/Show.S01/Show.S01E02.mkv"), dir.join("input/Movie.2010.mkv")];
+        for file in &files {
+            fs::write(file, b"media").unwrap();
+        }
+
+        let input = dir.join("input");
+        let output = dir.join("output");
+        let mut sort = Sort::try_parse_from(["sort", "--recursive", "--dry-run", "--input", input.to_str().unwrap(), "--output", output.to_str().unwrap()]).unwrap();
+        sort.run().unwrap();
+
+        assert!(files.iter().all(|file| file.exists()));
+        assert!(!output.exists());
+        assert_eq!(sort.journal, None);
+    }
+
+    #[test]
+    fn only_folders_of_specials_are_moved_whole() {
+        let dir = ScratchDir::new("recursive");
+        let input = dir.join("input");
+        let files = [
+            input.join("Inception.2010.1080p/Inception.2010.1080p.mkv"),
+            input.join("Sherlock Specials/Sherlock.S00E01.mkv"),
+            input.join("Show.S01E02.mkv"),
+        ];
+        for file in &files {
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, b"media").unwrap();
+        }
+
+        let output = dir.join("output");
+        let sort = Sort::try_parse_from(["sort", "--recursive", "--dry-run", "--input", input.to_str().unwrap(), "--output", output.to_str().unwrap()]).unwrap();
+        let (series_template, _) = sort.templates().unwrap();
+        let folder = |file: &Path| sort.specials_folder(file, &Episode::new(file, &sort.parse_rules), &series_template).unwrap();
+
+        // The input itself is never moved, a movie in a subfolder is sorted with the movie template
+        assert_eq!(folder(&files[0]), None);
+        assert_eq!(folder(&files[1]), Some((input.join("Sherlock Specials"), output.join("Series/Sherlock"))));
+        assert_eq!(folder(&files[2]), None);
+
+        let mut found: Vec<PathBuf> = sort.get_medias_from_input(&Journal::new()).unwrap().into_iter().map(|episode| episode.full_path).collect();
+        found.sort();
+        assert_eq!(found, vec![files[0].clone(), files[2].clone()]);
+        assert!(files.iter().all(|file| file.exists()));
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn uncertain_files_go_to_unsorted() {
+        let sort = |args: &[&str]| Sort::try_parse_from([&["sort", "--input", "/in", "--output", "/out"], args].concat()).unwrap();
+        let episode = |filename: &str, minutes: Option<f32>| {
+            let mut episode = Episode::from_name(filename, &Rules::default());
+            let mut score = episode.movie.clone();
+            if let Some(minutes) = minutes {
+                score.add_runtime(minutes * 60.0);
+            }
+            episode.classify(score);
+            episode
+        };
+        let destination = |sort: &Sort, episode: &Episode| sort.destination(episode, &sort.templates().unwrap()).unwrap();
+
+        let default = sort(&[]);
+        // A long runtime outweighs an absolute episode number
+        assert_eq!(destination(&default, &episode("Bleach 366.mkv", Some(103.0))), PathBuf::from("/out/Films/Bleach.mkv"));
+        assert_eq!(destination(&default, &episode("Bleach 366.mkv", None)), PathBuf::from("/out/Series/Bleach/S01/Bleach - E366.mkv"));
+        assert_eq!(destination(&default, &episode("Show.S01E01.mkv", Some(100.0))), PathBuf::from("/out/Unsorted/Show.S01E01.mkv"));
+
+        let strict = sort(&["--min-confidence", "50"]);
+        assert_eq!(destination(&strict, &episode("Bleach 366.mkv", None)), PathBuf::from("/out/Unsorted/Bleach 366.mkv"));
+        assert_eq!(destination(&strict, &episode("Show.S01E01.mkv", None)), PathBuf::from("/out/Series/Show/S01/Show - E01.mkv"));
+    }
+}
@@ -0,0 +1,253 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::cmd::sort::{is_on_same_drive, move_by_rename, MULTI_PROGRESS};
+use crate::cmd::undo::{Journal, MoveMethod};
+
+/// How a media file gets to the library.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Transfer {
+    /// Rename on the same drive, copy then delete across drives.
+    #[default]
+    Move,
+    /// Keep the source, e.g. so a torrent keeps seeding.
+    Copy,
+    /// Keep the source without using more space, copies across drives.
+    Hardlink,
+    /// Link to the source, which must stay where it is.
+    Symlink,
+    /// Copy-on-write clone on filesystems supporting it (Btrfs, XFS, APFS), copies elsewhere.
+    Reflink,
+}
+
+/// Size of the chunks copied and hashed at once.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Temporary name of a copy in progress, next to its destination.
+pub fn partial_path(to: &Path) -> PathBuf {
+    let mut partial = to.as_os_str().to_owned();
+    partial.push(".partial");
+    PathBuf::from(partial)
+}
+
+/// Hashes the next `len` bytes of `reader`.
+pub(super) fn hash_prefix<R: Read>(reader: R, len: u64) -> Result<blake3::Hasher> {
+    let mut hasher = blake3::Hasher::new();
+    let mut reader = reader.take(len);
+    let mut buffer = vec![0; CHUNK_SIZE];
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher)
+}
+
+/// Copies `from` to `to` without ever leaving a truncated file at `to`.
+///
+/// The data goes to a `.partial` file first, is synced to disk and checked against the source size and hash
+/// before being renamed into place. A `.partial` left by an interrupted copy is resumed when it matches
+/// the beginning of the source, and restarted otherwise.
+pub fn safe_copy(from: &Path, to: &Path) -> Result<()> {
+    safe_copy_with_progress(from, to, &ProgressBar::hidden())
+}
+
+/// `safe_copy` showing the progress of the file, the copied bytes are also added to `total`.
+pub fn safe_copy_with_progress(from: &Path, to: &Path, total: &ProgressBar) -> Result<()> {
+    let partial = partial_path(to);
+    let mut source = File::open(from).with_context(|| format!("Could not open {:?}", from))?;
+    let source_len = source.metadata()?.len();
+    let mut destination = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(&partial)
+        .with_context(|| format!("Could not create {:?}", partial))?;
+
+    let pb = MULTI_PROGRESS.add(ProgressBar::new(source_len));
+    pb.set_style(
+        ProgressStyle::default_bar()
+        .progress_chars("#>-")
+        .template("  {msg:30!} [{bar:25.green/white}] {bytes}/{total_bytes} ({binary_bytes_per_sec}, ETA {eta})")?
+    );
+    pb.set_message(from.file_name().unwrap_or_default().to_string_lossy().to_string());
+
+    let mut resumed = destination.metadata()?.len();
+    let mut source_hash = blake3::Hasher::new();
+    if resumed > 0 {
+        let source_prefix = hash_prefix(&mut source, resumed)?;
+        if resumed <= source_len && source_prefix.finalize() == hash_prefix(&mut destination, resumed)?.finalize() {
+            println!("Resuming the copy of {:?} at {} bytes", from, resumed);
+            source_hash = source_prefix;
+        } else {
+            println!("Restarting the copy of {:?}, the partial file does not match", from);
+            resumed = 0;
+            destination.set_len(0)?;
+        }
+        source.seek(SeekFrom::Start(resumed))?;
+        destination.seek(SeekFrom::Start(resumed))?;
+        pb.set_position(resumed);
+        total.inc(resumed);
+    }
+
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = source.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        source_hash.update(&buffer[..read]);
+        destination.write_all(&buffer[..read])?;
+        pb.inc(read as u64);
+        total.inc(read as u64);
+    }
+    destination.sync_all()?;
+
+    pb.set_message(format!("{} (verifying)", from.file_name().unwrap_or_default().to_string_lossy()));
+
+    // Read back from the disk, not from what was written
+    let copied_len = destination.metadata()?.len();
+    destination.seek(SeekFrom::Start(0))?;
+    let copied_hash = hash_prefix(&mut destination, copied_len)?.finalize();
+    drop(destination);
+
+    if copied_len != source_len || copied_hash != source_hash.finalize() {
+        _ = fs::remove_file(&partial);
+        pb.finish_and_clear();
+        bail!("Copy of {:?} to {:?} is corrupted, the source was kept", from, to);
+    }
+
+    fs::rename(&partial, to)?;
+    sync_dir(to.parent().unwrap());
+    pb.finish_and_clear();
+
+    Ok(())
+}
+
+/// Makes the rename durable, best effort as not every platform can open a directory.
+fn sync_dir(dir: &Path) {
+    if let Ok(dir) = File::open(dir) {
+        _ = dir.sync_all();
+    }
+}
+
+/// Transfers `from` to `to` and records it in the journal, returns the method that was used.
+/// The transferred bytes are added to `progress`, as they are copied or at once for renames and links.
+pub fn transfer_file(from: &Path, to: &Path, transfer: Transfer, journal: &Journal, progress: &ProgressBar) -> Result<MoveMethod> {
+    let to_dir = to.parent().unwrap();
+    let len = fs::metadata(from)?.len();
+
+    let method = match transfer {
+        Transfer::Move if is_on_same_drive(from, to_dir) => {
+            move_by_rename(from, to)?;
+            MoveMethod::Rename
+        }
+        Transfer::Move => {
+            safe_copy_with_progress(from, to, progress)?;
+            fs::remove_file(from)?;
+            MoveMethod::Copy
+        }
+        Transfer::Copy => {
+            safe_copy_with_progress(from, to, progress)?;
+            MoveMethod::CopyKeep
+        }
+        Transfer::Hardlink if is_on_same_drive(from, to_dir) => match fs::hard_link(from, to) {
+            Ok(()) => MoveMethod::Hardlink,
+            Err(e) => {
+                // e.g. FAT or network shares without hardlinks
+                println!("Could not hardlink {:?} ({}), copying it instead", from, e);
+                safe_copy_with_progress(from, to, progress)?;
+                MoveMethod::CopyKeep
+            }
+        },
+        Transfer::Hardlink => {
+            println!("{:?} is on another drive than {:?}, copying it instead of a hardlink", from, to_dir);
+            safe_copy_with_progress(from, to, progress)?;
+            MoveMethod::CopyKeep
+        }
+        Transfer::Symlink => {
+            symlink(&std::path::absolute(from)?, to).with_context(|| format!("Could not symlink {:?} to {:?}", from, to))?;
+            MoveMethod::Symlink
+        }
+        Transfer::Reflink => match reflink_copy::reflink(from, to) {
+            Ok(()) => MoveMethod::Reflink,
+            Err(_) => {
+                safe_copy_with_progress(from, to, progress)?;
+                MoveMethod::CopyKeep
+            }
+        },
+    };
+    journal.record_move(from, to, method);
+
+    if !matches!(method, MoveMethod::Copy | MoveMethod::CopyKeep) {
+        progress.inc(len);
+    }
+
+    Ok(method)
+}
+
+#[cfg(unix)]
+fn symlink(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(from, to)
+}
+
+#[cfg(windows)]
+fn symlink(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(from, to)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch_dir::ScratchDir;
+
+    #[test]
+    fn linked_transfers_keep_the_source() {
+        let dir = ScratchDir::new("transfer");
+        fs::create_dir_all(dir.join("library")).unwrap();
+        let source = dir.join("Show.S01E01.mkv");
+        fs::write(&source, b"episode").unwrap();
+        let journal = Journal::new();
+
+        for (transfer, name) in [(Transfer::Hardlink, "hardlink.mkv"), (Transfer::Symlink, "symlink.mkv"), (Transfer::Reflink, "reflink.mkv")] {
+            let destination = dir.join("library").join(name);
+            let method = transfer_file(&source, &destination, transfer, &journal, &ProgressBar::hidden()).unwrap();
+
+            assert!(method.keeps_source());
+            assert_eq!(fs::read(&destination).unwrap(), b"episode");
+        }
+        assert!(fs::symlink_metadata(dir.join("library/symlink.mkv")).unwrap().file_type().is_symlink());
+        assert!(source.exists());
+    }
+
+    #[test]
+    fn safe_copy_resumes_or_restarts_partial_copies() {
+        let dir = ScratchDir::new("safe-copy");
+        let source = dir.join("source.mkv");
+        let content: Vec<u8> = (0..3 * CHUNK_SIZE + 17).map(|i| (i % 251) as u8).collect();
+        fs::write(&source, &content).unwrap();
+
+        let resumed = dir.join("resumed.mkv");
+        fs::write(partial_path(&resumed), &content[..CHUNK_SIZE + 5]).unwrap();
+        safe_copy(&source, &resumed).unwrap();
+        assert_eq!(fs::read(&resumed).unwrap(), content);
+        assert!(!partial_path(&resumed).exists());
+
+        let restarted = dir.join("restarted.mkv");
+        fs::write(partial_path(&restarted), b"something else entirely").unwrap();
+        safe_copy(&source, &restarted).unwrap();
+        assert_eq!(fs::read(&restarted).unwrap(), content);
+
+        assert!(source.exists());
+    }
+}
@@ -0,0 +1,326 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::cmd::library::{library_index_path, LibraryIndex};
+use crate::cmd::profile::get_or_create_data_dir;
+use crate::cmd::sort::{move_by_copy, move_by_copy_recursive, move_by_rename, move_by_rename_recursive};
+use crate::cmd::{Run, Undo};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MoveMethod {
+    Rename,
+    Copy,
+    /// Copied while keeping the source, e.g. so a torrent keeps seeding.
+    #[serde(rename = "copy_keep")]
+    CopyKeep,
+    Hardlink,
+    Symlink,
+    /// Copy-on-write clone, sharing the data with the source.
+    Reflink,
+}
+
+impl MoveMethod {
+    /// The source was left in place, undoing only removes the destination.
+    pub fn keeps_source(&self) -> bool {
+        !matches!(self, MoveMethod::Rename | MoveMethod::Copy)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub method: MoveMethod,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct JournalFile {
+    started: u64,
+    entries: Vec<JournalEntry>,
+    created_dirs: Vec<PathBuf>,
+}
+
+/// Records every move of a sort run so it can be replayed backwards by `undo`.
+#[derive(Debug)]
+pub struct Journal {
+    started: u64,
+    entries: Mutex<Vec<JournalEntry>>,
+    created_dirs: Mutex<Vec<PathBuf>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Journals must stay valid whatever directory `undo` is run from.
+fn absolute<P: AsRef<Path>>(path: P) -> PathBuf {
+    std::path::absolute(path.as_ref()).unwrap_or_else(|_| path.as_ref().to_path_buf())
+}
+
+fn get_or_create_journals_dir() -> Result<PathBuf> {
+    get_or_create_data_dir("journals")
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Journal {
+            started: now(),
+            entries: Mutex::new(Vec::new()),
+            created_dirs: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn record_move<P: AsRef<Path>, Q: AsRef<Path>>(&self, source: P, destination: Q, method: MoveMethod) {
+        self.entries.lock().unwrap().push(JournalEntry {
+            source: absolute(source),
+            destination: absolute(destination),
+            method,
+            timestamp: now(),
+        });
+    }
+
+    /// Creates `dir` and its missing parents, remembering the ones that did not exist yet.
+    pub fn create_dir_all<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let missing: Vec<PathBuf> = dir
+            .as_ref()
+            .ancestors()
+            .take_while(|ancestor| !ancestor.as_os_str().is_empty() && !ancestor.exists())
+            .map(absolute)
+            .collect();
+
+        fs::create_dir_all(dir.as_ref())?;
+
+        // Parents first, so undo can remove them in reverse order
+        self.created_dirs.lock().unwrap().extend(missing.into_iter().rev());
+
+        Ok(())
+    }
+
+    /// Writes the journal to the MediaSort data directory, returns `None` if nothing was moved.
+    pub fn save(&self) -> Result<Option<PathBuf>> {
+        self.save_in(&get_or_create_journals_dir()?)
+    }
+
+    fn save_in(&self, dir: &Path) -> Result<Option<PathBuf>> {
+        let entries = self.entries.lock().unwrap().clone();
+        if entries.is_empty() {
+            return Ok(None);
+        }
+
+        let journal = JournalFile {
+            started: self.started,
+            entries,
+            created_dirs: self.created_dirs.lock().unwrap().clone(),
+        };
+
+        let mut path = dir.join(format!("{}.json", journal.started));
+        let mut suffix = 1;
+        while path.exists() {
+            path = dir.join(format!("{}-{}.json", journal.started, suffix));
+            suffix += 1;
+        }
+
+        fs::write(&path, serde_json::to_string_pretty(&journal)?)?;
+
+        Ok(Some(path))
+    }
+}
+
+fn list_journals() -> Result<Vec<PathBuf>> {
+    let mut journals = fs::read_dir(get_or_create_journals_dir()?)?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()?.to_str()? == "json" {
+                Some(path)
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<PathBuf>>();
+
+    journals.sort_by_key(|path| fs::metadata(path).and_then(|m| m.modified()).ok());
+
+    Ok(journals)
+}
+
+/// Moves of the latest runs, newest first.
+pub fn recent_moves(limit: usize) -> Result<Vec<JournalEntry>> {
+    let mut moves = Vec::new();
+
+    for journal in list_journals()?.iter().rev() {
+        if moves.len() >= limit {
+            break;
+        }
+        moves.extend(read_journal(journal)?.into_iter().rev());
+    }
+    moves.truncate(limit);
+
+    Ok(moves)
+}
+
+/// Moves recorded in a journal, in the order they happened.
+pub fn read_journal(path: &Path) -> Result<Vec<JournalEntry>> {
+    let content: JournalFile = serde_json::from_str(&fs::read_to_string(path)?)
+        .with_context(|| format!("Could not read journal {:?}", path))?;
+    Ok(content.entries)
+}
+
+fn move_back(entry: &JournalEntry) -> Result<()> {
+    let from = &entry.destination;
+    let to = &entry.source;
+
+    // The source was never touched, only the added file goes away
+    if entry.method.keeps_source() {
+        fs::remove_file(from)?;
+        return Ok(());
+    }
+
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match (entry.method, from.is_dir()) {
+        (MoveMethod::Rename, true) => move_by_rename_recursive(from, to)?,
+        (MoveMethod::Copy, true) => move_by_copy_recursive(from, to)?,
+        (MoveMethod::Rename, false) => move_by_rename(from, to)?,
+        (MoveMethod::Copy, false) => move_by_copy(from, to)?,
+        _ => unreachable!("kept sources are handled above"),
+    }
+
+    Ok(())
+}
+
+/// Replays a journal backwards, returns how many moves could not be undone.
+fn undo_journal(path: &Path, library: &LibraryIndex) -> Result<usize> {
+    let journal: JournalFile = serde_json::from_str(&fs::read_to_string(path)?)
+        .with_context(|| format!("Could not read journal {:?}", path))?;
+
+    let mut failed = 0;
+    for entry in journal.entries.iter().rev() {
+        // Not `exists`, which follows symlinks to sources deleted since
+        if fs::symlink_metadata(&entry.destination).is_err() {
+            println!("Missing {:?}, skipping", entry.destination);
+            failed += 1;
+            continue;
+        }
+        if entry.source.exists() && !entry.method.keeps_source() {
+            println!("{:?} already exists, skipping", entry.source);
+            failed += 1;
+            continue;
+        }
+
+        move_back(entry)?;
+        library.remove(&entry.destination);
+        if entry.method.keeps_source() {
+            println!("Removed {}", entry.destination.display());
+        } else {
+            println!("{} -> {}", entry.destination.display(), entry.source.display());
+        }
+    }
+
+    for dir in journal.created_dirs.iter().rev() {
+        // Only removes empty directories, anything added since the run is kept
+        _ = fs::remove_dir(dir);
+    }
+
+    Ok(failed)
+}
+
+impl Run for Undo {
+    fn run(&mut self) -> Result<()> {
+        let journals = list_journals()?;
+
+        if self.list {
+            if journals.is_empty() {
+                println!("No journals found!");
+            } else {
+                println!("Journals:");
+                for journal in journals {
+                    let content: JournalFile = serde_json::from_str(&fs::read_to_string(&journal)?)?;
+                    println!(
+                        "  - {} ({} moves)",
+                        journal.file_stem().unwrap().to_str().unwrap(),
+                        content.entries.len()
+                    );
+                }
+            }
+            return Ok(());
+        }
+
+        let journal_path = match &self.journal {
+            Some(name) => {
+                let path = get_or_create_journals_dir()?.join(format!("{}.json", name));
+                if !path.exists() {
+                    bail!("Journal not found: {}", name);
+                }
+                path
+            }
+            None => journals.last().cloned().context("No journal to undo")?,
+        };
+
+        let library = LibraryIndex::load(library_index_path()?);
+        let failed = undo_journal(&journal_path, &library)?;
+        library.save()?;
+
+        if failed > 0 {
+            println!("\n{} moves could not be undone, journal kept at {:?}", failed, journal_path);
+            return Ok(());
+        }
+
+        fs::rename(&journal_path, journal_path.with_extension("undone"))?;
+
+        println!("\nRun {:?} successfully undone", journal_path.file_stem().unwrap());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch_dir::ScratchDir;
+
+    #[test]
+    fn journaled_moves_are_undone() {
+        let dir = ScratchDir::new("undo");
+        fs::create_dir_all(dir.join("downloads")).unwrap();
+        let renamed = dir.join("downloads/Show.S01E01.mkv");
+        let seeded = dir.join("downloads/Show.S01E02.mkv");
+        fs::write(&renamed, b"first").unwrap();
+        fs::write(&seeded, b"second").unwrap();
+
+        let journal = Journal::new();
+        let season = dir.join("library/Show/S01");
+        journal.create_dir_all(&season).unwrap();
+        move_by_rename(&renamed, &season.join("Show - E01.mkv")).unwrap();
+        journal.record_move(&renamed, season.join("Show - E01.mkv"), MoveMethod::Rename);
+        fs::copy(&seeded, season.join("Show - E02.mkv")).unwrap();
+        journal.record_move(&seeded, season.join("Show - E02.mkv"), MoveMethod::CopyKeep);
+        let path = journal.save_in(&dir).unwrap().unwrap();
+
+        let library = LibraryIndex::load(dir.join("library.json"));
+        assert_eq!(undo_journal(&path, &library).unwrap(), 0);
+        assert_eq!(fs::read(&renamed).unwrap(), b"first");
+        assert_eq!(fs::read(&seeded).unwrap(), b"second");
+        assert!(!dir.join("library").exists());
+
+        // Nothing left to move back
+        assert_eq!(undo_journal(&path, &library).unwrap(), 2);
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+use crate::cmd::undo::Journal;
+use crate::cmd::Sort;
+use crate::episode::Episode;
+
+/// Default number of seconds a file must stay unchanged before it is sorted.
+pub const DEFAULT_SETTLE_TIME: u64 = 10;
+
+/// Suffixes of files still being downloaded, e.g. `Show.S01E01.mkv.!qB`.
+const PARTIAL_SUFFIXES: &[&str] = &[".part", ".!qb", ".crdownload", ".partial", ".tmp"];
+
+/// How often pending files are checked.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+fn is_partial(path: &Path) -> bool {
+    let filename = path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_lowercase();
+    PARTIAL_SUFFIXES.iter().any(|suffix| filename.ends_with(suffix))
+}
+
+/// Size and modification time, a file is complete once they stop changing.
+fn signature(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = fs::metadata(path).ok()?;
+    metadata.is_file().then(|| (metadata.len(), metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)))
+}
+
+/// A media file waiting for its download to finish.
+struct Pending {
+    signature: Option<(u64, SystemTime)>,
+    changed: Instant,
+}
+
+impl Sort {
+    /// Sorts media files as they land in the input directory, until interrupted.
+    /// Files are queued on every write and sorted once unchanged for `settle_time` seconds,
+    /// so bursts of events from torrent clients only trigger one sort.
+    pub(super) fn watch(&self) -> Result<()> {
+        let input = self.input.clone().unwrap();
+        let settle_time = Duration::from_secs(self.settle_time);
+
+        let pool = self.thread_pool()?;
+
+        let (sender, receiver) = channel::<notify::Result<Event>>();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        let mode = if self.recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        watcher
+            .watch(&input, mode)
+            .with_context(|| format!("Could not watch {:?}", input))?;
+
+        let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+
+        for path in self.existing_files(&input)? {
+            self.queue(&mut pending, path);
+        }
+
+        println!("Watching {:?} for new medias, press Ctrl+C to stop", input);
+
+        loop {
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(event)) => self.handle_event(&mut pending, event),
+                Ok(Err(e)) => println!("Watch error: {:#}", e),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            let ready = Self::take_ready(&mut pending, settle_time);
+            if !ready.is_empty() {
+                pool.install(|| self.sort_ready(ready));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_event(&self, pending: &mut HashMap<PathBuf, Pending>, event: Event) {
+        match event.kind {
+            EventKind::Create(_)
+            | EventKind::Modify(ModifyKind::Data(_))
+            | EventKind::Modify(ModifyKind::Any)
+            | EventKind::Modify(ModifyKind::Name(RenameMode::To))
+            | EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+                for path in event.paths {
+                    self.queue(pending, path);
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                // `Show.mkv.part` renamed to `Show.mkv` once complete
+                let mut paths = event.paths.into_iter();
+                if let Some(from) = paths.next() {
+                    pending.remove(&from);
+                }
+                for path in paths {
+                    self.queue(pending, path);
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) | EventKind::Remove(_) => {
+                for path in event.paths {
+                    pending.remove(&path);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Files downloaded while MediaSort was not running, in the subfolders too with `--recursive`.
+    fn existing_files(&self, input: &Path) -> Result<Vec<PathBuf>> {
+        if !self.recursive {
+            return fs::read_dir(input)?.map(|entry| Ok(entry?.path())).collect();
+        }
+
+        let files = Mutex::new(Vec::new());
+        self.visit_dirs(input, &|path| {
+            files.lock().unwrap().push(path.to_path_buf());
+            Ok(())
+        })?;
+        Ok(files.into_inner().unwrap())
+    }
+
+    /// Queues `path`, or restarts its settle time when it is already pending.
+    fn queue(&self, pending: &mut HashMap<PathBuf, Pending>, path: PathBuf) {
+        if is_partial(&path) || !self.is_media(&path) || !path.is_file() {
+            return;
+        }
+
+        if !pending.contains_key(&path) {
+            self.verbose(&format!("Waiting for {:?} to be complete", path));
+        }
+        pending.insert(path.clone(), Pending { signature: signature(&path), changed: Instant::now() });
+    }
+
+    /// Removes and returns the files unchanged for `settle_time`, still growing files are kept.
+    fn take_ready(pending: &mut HashMap<PathBuf, Pending>, settle_time: Duration) -> Vec<PathBuf> {
+        let mut ready = Vec::new();
+
+        pending.retain(|path, file| {
+            let signature = signature(path);
+            if signature.is_none() {
+                return false;
+            }
+            if signature != file.signature {
+                file.signature = signature;
+                file.changed = Instant::now();
+                return true;
+            }
+            if file.changed.elapsed() < settle_time {
+                return true;
+            }
+
+            ready.push(path.clone());
+            false
+        });
+
+        ready.sort();
+        ready
+    }
+
+    /// Sorts one batch of complete files with its own journal, errors don't stop the watch.
+    fn sort_ready(&self, paths: Vec<PathBuf>) {
+        let journal = Journal::new();
+        let episodes: Vec<Episode> = paths.iter().map(|path| Episode::new(path, &self.parse_rules)).collect();
+
+        match self.sort_episodes(episodes, &journal) {
+            Ok(()) => println!("Sorted {} new media files", paths.len()),
+            Err(e) => println!("Could not sort {:?}: {:#}", paths, e),
+        }
+
+        match journal.save() {
+            Ok(Some(path)) => println!("Run journal saved to {:?}", path),
+            Ok(None) => {}
+            Err(e) => println!("Could not save the run journal: {:#}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::scratch_dir::ScratchDir;
+
+    #[test]
+    fn partial_downloads_are_ignored() {
+        assert!(is_partial(Path::new("/downloads/Show.S01E01.mkv.part")));
+        assert!(is_partial(Path::new("/downloads/Show.S01E01.mkv.!qB")));
+        assert!(is_partial(Path::new("/downloads/Show.S01E01.mkv.crdownload")));
+        assert!(!is_partial(Path::new("/downloads/Show.S01E01.mkv")));
+    }
+
+    #[test]
+    fn recursive_watches_queue_existing_files_of_subfolders() {
+        let dir = ScratchDir::new("watch-existing");
+        fs::create_dir_all(dir.join("Show.S01")).unwrap();
+        fs::write(dir.join("Movie.2010.mkv"), b"movie").unwrap();
+        fs::write(dir.join("Show.S01/Show.S01E01.mkv"), b"episode").unwrap();
+
+        let queued = |recursive: bool| {
+            let mut args = vec!["sort", "--input", dir.to_str().unwrap(), "--output", "/out"];
+            if recursive {
+                args.push("--recursive");
+            }
+            let sort = Sort::try_parse_from(args).unwrap();
+
+            let mut pending = HashMap::new();
+            for path in sort.existing_files(&dir).unwrap() {
+                sort.queue(&mut pending, path);
+            }
+            let mut queued: Vec<PathBuf> = pending.into_keys().collect();
+            queued.sort();
+            queued
+        };
+
+        assert_eq!(queued(false), vec![dir.join("Movie.2010.mkv")]);
+        assert_eq!(queued(true), vec![dir.join("Movie.2010.mkv"), dir.join("Show.S01/Show.S01E01.mkv")]);
+    }
+
+    #[test]
+    fn growing_files_wait_for_the_settle_time() {
+        let dir = ScratchDir::new("watch");
+        let path = dir.join("Show.S01E01.mkv");
+        fs::write(&path, b"first").unwrap();
+
+        let mut pending = HashMap::new();
+        pending.insert(path.clone(), Pending { signature: signature(&path), changed: Instant::now() });
+        assert!(Sort::take_ready(&mut pending, Duration::from_secs(60)).is_empty());
+
+        fs::write(&path, b"first and second").unwrap();
+        assert!(Sort::take_ready(&mut pending, Duration::ZERO).is_empty());
+        assert_eq!(Sort::take_ready(&mut pending, Duration::ZERO), vec![path.clone()]);
+        assert!(pending.is_empty());
+    }
+}
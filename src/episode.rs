@@ -1,10 +1,61 @@
-use std::path::PathBuf;
-use std::vec;
+use std::path::{Path, PathBuf};
 
-use anyhow::{bail, Result};
-
-use regex::Regex;
 use ffprobe::ffprobe;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::release::{Release, ReleaseTags};
+use crate::rules::Rules;
+
+static MOVIE_WORD: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)\b(?:film|movie)\b").unwrap());
+
+/// Whether a file is a movie, from cues that each add points toward a movie or an episode.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MovieScore {
+    /// Positive for a movie, negative for an episode.
+    pub score: i32,
+    /// Every cue with its points, e.g. `+25 year without season and episode numbers`.
+    pub reasons: Vec<String>,
+}
+
+impl MovieScore {
+    fn add(&mut self, points: i32, reason: &str) {
+        self.score += points;
+        self.reasons.push(format!("{:+} {}", points, reason));
+    }
+
+    pub fn is_movie(&self) -> bool {
+        self.score > 0
+    }
+
+    /// From 0 to 100, how far the cues lean one way.
+    pub fn confidence(&self) -> u32 {
+        self.score.unsigned_abs().min(100)
+    }
+
+    /// Movies run longer than 70 minutes, episodes shorter than 40, long finales and specials are in between.
+    pub fn add_runtime(&mut self, seconds: f32) {
+        let minutes = (seconds / 60.0).round() as u32;
+        let points = match minutes {
+            70.. => 50,
+            40..=69 => 0,
+            _ => -40,
+        };
+        self.add(points, &format!("runtime of {} min", minutes));
+    }
+
+    /// Accuracy of the best series and movie matches of the name on the metadata providers.
+    pub fn add_lookup(&mut self, series: Option<i64>, movie: Option<i64>) {
+        match (series, movie) {
+            (Some(series), Some(movie)) if movie > series => self.add(15, "closer movie match"),
+            (Some(series), Some(movie)) if series > movie => self.add(-15, "closer series match"),
+            (Some(_), None) => self.add(-30, "only matches a series"),
+            (None, Some(_)) => self.add(30, "only matches a movie"),
+            _ => self.add(0, "no closer series or movie match"),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Episode {
@@ -14,172 +65,247 @@ pub struct Episode {
     pub extension: String,
 
     pub name: String,
+    /// Release year, from the file name or a metadata lookup.
+    pub year: Option<String>,
+    /// Episode title, e.g. `Pilot` for `Show.S01E01.Pilot.720p.mkv`.
+    pub title: Option<String>,
     pub season: u32,
     pub episode: u32,
+    /// Last episode of a multi-episode file, e.g. 6 for `S02E05E06`.
+    pub last_episode: Option<u32>,
+    /// Episode number counted from the start of the show, when the release has no season.
+    pub absolute_episode: Option<u32>,
+    /// Resolution, source, codecs, languages, group and edition of the release.
+    pub tags: ReleaseTags,
+    /// User rule the file name matched, `None` when only the built-in parser was used.
+    pub rule: Option<String>,
     pub is_movie: bool,
+    /// Why `is_movie` was decided, and how confidently.
+    pub movie: MovieScore,
 }
 
 impl Episode {
-    pub fn new(full_path: &PathBuf) -> Self {
+    pub fn new(full_path: &Path, rules: &Rules) -> Self {
+        let mut ep = Self::parse(full_path, rules);
+        ep.classify(ep.movie_score(true));
+
+        ep
+    }
+
+    /// Parses the file name alone, the file doesn't need to exist and is not probed:
+    /// `is_movie` only comes from the name.
+    pub fn parse(full_path: &Path, rules: &Rules) -> Self {
         let filename = full_path.file_name().unwrap().to_str().unwrap();
-        let filename_clean = Self::clean_filename(filename);
+        let mut ep = Self::from_name(filename, rules);
+        ep.full_path = full_path.to_path_buf();
+
+        ep
+    }
+
+    /// Parses a file name with the given rules, without any file.
+    pub fn from_name(filename: &str, rules: &Rules) -> Self {
+        let release = Release::parse_with(filename, rules);
 
         let mut ep = Episode {
-            full_path: full_path.clone(),
+            full_path: PathBuf::from(filename),
             filename: filename.to_string(),
-            filename_clean: filename_clean.clone(),
+            filename_clean: release.clean,
             extension: "unknown".to_string(),
 
-            name: "unknown".to_string(),
-            season: 0,
-            episode: 0,
+            name: Some(release.title).filter(|name| !name.is_empty()).unwrap_or("unknown".to_string()),
+            year: release.year.map(|year| year.to_string()),
+            title: release.episode_title,
+            season: release.season,
+            episode: release.episode,
+            last_episode: release.last_episode,
+            absolute_episode: release.absolute_episode,
+            tags: release.tags,
+            rule: release.rule,
             is_movie: false,
+            movie: MovieScore::default(),
         };
 
-        ep.fetch_infos();
+        ep.extension = ep.extract_extension();
+        ep.classify(ep.movie_score(false));
 
         ep
     }
 
-    fn fetch_infos(&mut self) {
-        self.name = self.extract_series_name().unwrap();
-        self.season = self.extract_season();
-        self.episode = self.extract_episode();
-        self.extension = self.extract_extension();
-        self.is_movie = self.is_movie().unwrap();
-    }
-
-    fn clean_filename(filename_to_clean: &str) -> String {
-        let mut cleaned = filename_to_clean.to_string();
-
-        cleaned = cleaned.replace(&['.', '_', '-', '+'][..], " ");
+    /// Completes the episode with the name of the release it belongs to, e.g. `Show.S02.1080p/01.mkv`
+    /// has neither a series name nor a season in its file name.
+    pub fn apply_release_name(&mut self, release: &str, rules: &Rules) {
+        let release = Release::parse_with(release, rules);
 
-        //remove unwanted patterns as [] and () content
-        cleaned = Regex::new(r"\[.*?\]").unwrap().replace_all(&cleaned, "").to_string();
-        cleaned = Regex::new(r"\(.*?\)").unwrap().replace_all(&cleaned, "").to_string();
-        cleaned = Regex::new(r"\b(net|fit|ws|tv|TV|ec|co|vip|cc|red|NanDesuKa|FANSUB|tokyo|WEBRip|DL|H264|Light|com|org|info|www|com|vostfree|VOSTFR|boats|uno|Wawacity|wawacity|WEB|TsundereRaws|1080p|720p|x264|AAC|Tsundere|Raws|fit|ws|tv|TV|ec)\b").unwrap().replace_all(&cleaned, "").to_string();
-        cleaned.split_whitespace().collect::<Vec<&str>>().join(" ");
+        let has_name = self.name.chars().any(|c| c.is_alphabetic());
+        if !has_name && !release.title.is_empty() {
+            self.name = release.title;
+        }
+        if self.year.is_none() {
+            self.year = release.year.map(|year| year.to_string());
+        }
 
-        cleaned = cleaned.trim().to_string();
+        // An episode counted from the start of the season, not the show
+        if self.absolute_episode.is_some() && release.absolute_episode.is_none() && release.season > 0 {
+            self.season = release.season;
+            self.absolute_episode = None;
+        }
 
-        cleaned
+        // Files of a season pack are often named `01.mkv`, the tags are on the release
+        let tags = release.tags;
+        self.tags.resolution = self.tags.resolution.or(tags.resolution);
+        self.tags.source = self.tags.source.or(tags.source);
+        self.tags.video_codec = self.tags.video_codec.or(tags.video_codec);
+        self.tags.audio_codec = self.tags.audio_codec.or(tags.audio_codec);
+        self.tags.group = self.tags.group.take().or(tags.group);
+        self.tags.edition = self.tags.edition.take().or(tags.edition);
+        if self.tags.languages.is_empty() {
+            self.tags.languages = tags.languages;
+        }
     }
 
-    fn extract_series_name(&self) -> Result<String> {
-
-        //use first string operation if possible to avoid regex
-        let name: Vec<&str> = self.filename_clean.split_whitespace().collect();
-
-        for i in 0..name.len() {
-            if name[i].starts_with('S') && name[i].len() > 1 && name[i].chars().skip(1).all(char::is_numeric) {
-                return Ok(name[..i].join(" ").trim().to_string());
-            } else if name[i].starts_with('E') && name[i].len() > 1 && name[i].chars().skip(1).all(char::is_numeric) {
-                return Ok(name[..i].join(" ").trim().to_string());
-            }
+    /// Episode part of the file name, `E05` or `E05-E06` for multi-episode files.
+    pub fn episode_tag(&self) -> String {
+        match self.last_episode {
+            Some(last_episode) => format!("E{:02}-E{:02}", self.episode, last_episode),
+            None => format!("E{:02}", self.episode),
         }
+    }
 
-        let name_patterns = vec![
-            r"(.+?)(S\d{1,2}E\d{1,2}|S\d{1,2})",
-            r"(.+?)(S\d{1,2} \d{1,2})",
-            r"(.+?)(E\d{1,2})",
-            r"(.+?)(\d{1,3})",
-            r"(.+?)(Film|Movie)",
-            r"(.+)",
-        ];
-
-        for pattern in name_patterns {
-            let re = Regex::new(pattern).unwrap();
-            if let Some(captures) = re.captures(&self.filename_clean) {
-                if let Some(name) = captures.get(1) {
-                    return Ok(name.as_str().trim().to_string());
-                }
-            }
-        }
+    fn extract_extension(&self) -> String {
+        let extension = self
+            .full_path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("unknown")
+            .to_string();
 
-        bail!("Name not found")
+        extension
     }
 
-    fn extract_season(&self) -> u32 {
+    pub fn classify(&mut self, movie: MovieScore) {
+        self.is_movie = movie.is_movie();
+        self.movie = movie;
+    }
 
-        //use first string operation if possible to avoid regex
-        let season: Vec<&str> = self.filename_clean.split_whitespace().collect();
-        for i in 0..season.len() {
-            if season[i].starts_with('S') && season[i].len() > 1 && season[i].chars().skip(1).all(char::is_numeric) {
-                return season[i].chars().skip(1).collect::<String>().parse::<u32>().unwrap_or(1);
-            }
+    /// Scores the release name cues, then the runtime with `probe`.
+    pub fn movie_score(&self, probe: bool) -> MovieScore {
+        let mut movie = MovieScore::default();
+
+        let season_episode = self.episode > 0 && self.absolute_episode.is_none();
+        if season_episode {
+            movie.add(-60, "season and episode numbers");
+        } else if self.absolute_episode.is_some() {
+            movie.add(-35, "episode number without a season");
+        } else if self.season > 0 {
+            movie.add(-20, "season number");
+        } else {
+            movie.add(40, "no episode number");
+        }
+        if self.year.is_some() && !season_episode {
+            movie.add(25, "year without season and episode numbers");
+        }
+        if self.tags.edition.is_some() {
+            movie.add(20, "edition tag");
+        }
+        if MOVIE_WORD.is_match(&self.filename) {
+            movie.add(50, "named Film or Movie");
         }
 
-
-        let season_pattern = r"S(\d{1,2})(?:E\d{1,2})?";
-        let re = Regex::new(season_pattern).unwrap();
-        if let Some(captures) = re.captures(&self.filename_clean) {
-            if let Some(season) = captures.get(1) {
-                return season.as_str().parse::<u32>().unwrap_or(1);
+        if probe {
+            match ffprobe(&self.full_path) {
+                Ok(metadata) => match metadata.format.duration.and_then(|duration| duration.parse::<f32>().ok()) {
+                    Some(duration) => movie.add_runtime(duration),
+                    None => movie.add(0, "unknown runtime"),
+                },
+                Err(_) => movie.add(0, "unknown runtime, ffprobe failed"),
             }
         }
 
-        0
+        movie
     }
+}
 
-    fn extract_episode(&self) -> u32 {
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        //use first string operation if possible to avoid regex
-        let episode: Vec<&str> = self.filename_clean.split_whitespace().collect();
-        for i in 0..episode.len() {
-            if episode[i].starts_with('E') && episode[i].len() > 1 && episode[i].chars().skip(1).all(char::is_numeric) {
-                return episode[i].chars().skip(1).collect::<String>().parse::<u32>().unwrap_or(1);
-            }
-        }
+    /// `file | series | year | season | episode | movie` lines, `#` starts a comment.
+    const CORPUS: &str = include_str!("../tests/release_names.txt");
 
+    fn parsed(episode: &Episode) -> String {
+        let number = match episode.last_episode {
+            Some(last_episode) => format!("{}-{}", episode.episode, last_episode),
+            None => episode.episode.to_string(),
+        };
+        [
+            episode.filename.as_str(),
+            episode.name.as_str(),
+            episode.year.as_deref().unwrap_or("-"),
+            &episode.season.to_string(),
+            &number,
+            if episode.is_movie { "yes" } else { "no" },
+        ]
+        .join(" | ")
+    }
 
-        let episode_pattern = r"(?:S\d{1,2}E(\d{1,2}))|(?:E(\d{1,2}))|(?:\b(\d{1,3})\b)";
-        let re = Regex::new(episode_pattern).unwrap();
-        if let Some(captures) = re.captures(&self.filename_clean) {
-            if let Some(episode) = captures.get(1) {
-                return episode.as_str().parse::<u32>().unwrap_or(1);
-            } else if let Some(episode) = captures.get(2) {
-                return episode.as_str().parse::<u32>().unwrap_or(1);
-            } else if let Some(episode) = captures.get(3) {
-                return episode.as_str().parse::<u32>().unwrap_or(1);
-            }
-        }
-
-        0
+    #[test]
+    fn release_name_corpus() {
+        let rules = Rules::default();
+        let lines: Vec<&str> = CORPUS.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).collect();
+
+        let failures: Vec<String> = lines
+            .iter()
+            .filter_map(|line| {
+                let filename = line.split(" | ").next().unwrap();
+                let got = parsed(&Episode::from_name(filename, &rules));
+                (got != *line).then(|| format!("expected {}\n     got {}", line, got))
+            })
+            .collect();
+
+        assert!(lines.len() > 50);
+        assert!(failures.is_empty(), "{} of {} names parsed differently:\n{}", failures.len(), lines.len(), failures.join("\n"));
     }
 
-    fn extract_extension(&self) -> String {
-        let extension = self
-            .full_path
-            .extension()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_string();
+    #[test]
+    fn short_bare_numbers_are_part_of_the_title() {
+        let rules = Rules::default();
+        let parsed = |filename: &str| {
+            let episode = Episode::from_name(filename, &rules);
+            (episode.name, episode.season, episode.episode, episode.absolute_episode)
+        };
 
-        extension
+        assert_eq!(parsed("Toy Story 3.mkv"), ("Toy Story 3".to_string(), 0, 0, None));
+        assert_eq!(parsed("Show 05.mkv"), ("Show".to_string(), 1, 5, Some(5)));
+        assert_eq!(parsed("Bleach 366 VOSTFR.mp4"), ("Bleach".to_string(), 1, 366, Some(366)));
+        assert_eq!(parsed("[Group] Show - 7 [1080p].mkv"), ("Show".to_string(), 1, 7, Some(7)));
+        assert_eq!(parsed("Sherlock.S00E01.mkv"), ("Sherlock".to_string(), 0, 1, None));
     }
 
-    fn is_movie(&self) -> Result<bool> {
-        if self.filename.contains("Film") || self.filename.contains("Movie") {
-            return Ok(true);
-        }
-        if self.season == 0 && self.episode == 0 {
-            return Ok(true);
-        }
-
-        match ffprobe(&self.full_path) {
-            Ok(metadata) => {
-                if let Some(duration) = metadata.format.duration {
-                    if duration.parse::<f32>().unwrap_or(0.0) > 3000.0 {
-                        return Ok(true);
-                    }
-                }
-            }
-            Err(e) => {
-                bail!("Error while parsing file with ffprobe: {:?}", e);
+    #[test]
+    fn movies_are_scored_from_the_name_and_runtime() {
+        let rules = Rules::default();
+        let score = |filename: &str, runtime: Option<f32>| {
+            let mut movie = Episode::from_name(filename, &rules).movie_score(false);
+            if let Some(runtime) = runtime {
+                movie.add_runtime(runtime);
             }
-        }
+            (movie.is_movie(), movie.confidence())
+        };
 
-        Ok(false)
+        // A long anime finale stays an episode
+        assert_eq!(score("Show.S01E12.1080p.mkv", Some(55.0 * 60.0)), (false, 60));
+        assert_eq!(score("[Group] Show - 24 [1080p].mkv", Some(24.0 * 60.0)), (false, 75));
+        assert_eq!(score("Blade.Runner.2049.1080p.BluRay.mkv", None), (true, 40));
+        assert_eq!(score("Inception.2010.1080p.BluRay.mkv", Some(148.0 * 60.0)), (true, 100));
+        assert_eq!(score("Toy Story 3.mkv", Some(103.0 * 60.0)), (true, 90));
+        // An absolute episode number reads as an episode until the runtime says otherwise
+        assert_eq!(score("Bleach 366.mkv", None), (false, 35));
+        assert_eq!(score("Bleach 366.mkv", Some(103.0 * 60.0)), (true, 15));
+
+        let mut movie = Episode::from_name("Bleach 366.mkv", &rules).movie_score(false);
+        movie.add_lookup(None, Some(95));
+        assert_eq!((movie.is_movie(), movie.confidence()), (false, 5));
+        movie.add_runtime(103.0 * 60.0);
+        assert_eq!((movie.is_movie(), movie.confidence()), (true, 45));
     }
 }
@@ -1,10 +1,16 @@
 #[allow(unused_imports)]
 #[allow(dead_code)]
+#[allow(clippy::module_inception)]
 mod cmd;
 mod error;
 mod search;
 
 mod episode;
+mod naming;
+mod release;
+mod rules;
+#[cfg(test)]
+mod scratch_dir;
 
 use std::io::{self, Write};
 use std::process::ExitCode;
@@ -41,6 +47,3 @@ fn main() -> ExitCode {
     ExitCode::SUCCESS
 }
 
-fn main() {
-
-}
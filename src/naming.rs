@@ -0,0 +1,390 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+
+use crate::episode::Episode;
+
+/// Default series layout, `Series/Show/S01/Show - E05.mkv`.
+pub const DEFAULT_SERIES_TEMPLATE: &str =
+    "Series/{series}[ ({year})]/S{season:02}/{series}[ ({year})] - E{episode:02}[-E{last_episode:02}].{ext}";
+/// Default movie layout, `Films/Movie.mkv`.
+pub const DEFAULT_MOVIE_TEMPLATE: &str = "Films/{series}[ ({year})].{ext}";
+
+/// Built-in layouts, following each media server's naming conventions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// `Series/Show/S01/Show - E01.mkv` and `Films/Movie.mkv`.
+    #[default]
+    Mediasort,
+    /// `TV Shows/Show (Year)/Season 01/Show (Year) - s01e01.mkv` and `Movies/Movie (Year)/Movie (Year).mkv`.
+    Plex,
+    /// `Shows/Show (Year)/Season 01/Show S01E01.mkv` and `Movies/Movie (Year)/Movie (Year).mkv`.
+    Jellyfin,
+    /// `TV Shows/Show (Year)/Season 1/Show S01E01.mkv` and `Movies/Movie (Year)/Movie (Year).mkv`.
+    Kodi,
+}
+
+impl Layout {
+    /// Series and movie templates of the layout. Specials (season 0) land in `Season 00`, or `Season 0` for Kodi.
+    pub fn templates(&self) -> (&'static str, &'static str) {
+        const MOVIE_FOLDER: &str = "Movies/{series}[ ({year})]/{series}[ ({year})].{ext}";
+
+        match self {
+            Layout::Mediasort => (DEFAULT_SERIES_TEMPLATE, DEFAULT_MOVIE_TEMPLATE),
+            Layout::Plex => (
+                "TV Shows/{series}[ ({year})]/Season {season:02}/{series}[ ({year})] - s{season:02}e{episode:02}[-e{last_episode:02}].{ext}",
+                MOVIE_FOLDER,
+            ),
+            Layout::Jellyfin => (
+                "Shows/{series}[ ({year})]/Season {season:02}/{series} S{season:02}E{episode:02}[-E{last_episode:02}][ - {title}].{ext}",
+                MOVIE_FOLDER,
+            ),
+            Layout::Kodi => (
+                "TV Shows/{series}[ ({year})]/Season {season}/{series} S{season:02}E{episode:02}[E{last_episode:02}].{ext}",
+                MOVIE_FOLDER,
+            ),
+        }
+    }
+}
+
+const TEXT_FIELDS: &[&str] = &[
+    "series", "title", "year", "ext", "resolution", "source", "video_codec", "audio_codec", "languages", "group", "edition",
+];
+const NUMBER_FIELDS: &[&str] = &["season", "episode", "last_episode", "absolute"];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Text(String),
+    Field { name: String, width: usize },
+    /// Dropped when one of its fields has no value, e.g. `[ ({year})]`.
+    Optional(Vec<Token>),
+}
+
+/// Destination path template, relative to the output directory.
+///
+/// `{field}` is replaced by the episode value, `{field:02}` pads numbers with zeros,
+/// `[...]` sections are dropped when a field inside has no value and `/` separates folders.
+#[derive(Clone, Debug)]
+pub struct Template {
+    tokens: Vec<Token>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template> {
+        let mut tokens: Vec<Token> = Vec::new();
+        let mut optional: Option<Vec<Token>> = None;
+        let mut text = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let current = optional.as_mut().unwrap_or(&mut tokens);
+                    if !text.is_empty() {
+                        current.push(Token::Text(std::mem::take(&mut text)));
+                    }
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => field.push(c),
+                            None => bail!("Unclosed '{{' in template {:?}", template),
+                        }
+                    }
+                    current.push(Self::parse_field(&field, template)?);
+                }
+                '}' => bail!("Unexpected '}}' in template {:?}", template),
+                '[' => {
+                    if optional.is_some() {
+                        bail!("Nested '[' in template {:?}", template);
+                    }
+                    if !text.is_empty() {
+                        tokens.push(Token::Text(std::mem::take(&mut text)));
+                    }
+                    optional = Some(Vec::new());
+                }
+                ']' => {
+                    let Some(mut section) = optional.take() else {
+                        bail!("Unexpected ']' in template {:?}", template);
+                    };
+                    if !text.is_empty() {
+                        section.push(Token::Text(std::mem::take(&mut text)));
+                    }
+                    tokens.push(Token::Optional(section));
+                }
+                c => text.push(c),
+            }
+        }
+
+        if optional.is_some() {
+            bail!("Unclosed '[' in template {:?}", template);
+        }
+        if !text.is_empty() {
+            tokens.push(Token::Text(text));
+        }
+
+        Ok(Template { tokens })
+    }
+
+    fn parse_field(field: &str, template: &str) -> Result<Token> {
+        let (name, width) = match field.split_once(':') {
+            Some((name, width)) => match width.parse::<usize>() {
+                Ok(width) => (name.trim(), width),
+                Err(_) => bail!("Invalid width {:?} for {{{}}} in template {:?}", width, name, template),
+            },
+            None => (field.trim(), 0),
+        };
+
+        if NUMBER_FIELDS.contains(&name) || (TEXT_FIELDS.contains(&name) && width == 0) {
+            Ok(Token::Field { name: name.to_string(), width })
+        } else if TEXT_FIELDS.contains(&name) {
+            bail!("{{{}}} is not a number and can't be padded in template {:?}", name, template)
+        } else {
+            bail!(
+                "Unknown field {{{}}} in template {:?}, expected one of: {}",
+                name,
+                template,
+                TEXT_FIELDS.iter().chain(NUMBER_FIELDS).cloned().collect::<Vec<&str>>().join(", ")
+            )
+        }
+    }
+
+    /// Renders the path of `episode`, relative to the output directory.
+    pub fn render(&self, episode: &Episode) -> Result<PathBuf> {
+        let rendered = Self::render_sections(&self.tokens, episode);
+
+        let mut path = PathBuf::new();
+        for component in rendered.split('/').map(str::trim).filter(|component| !component.is_empty()) {
+            if component == "." || component == ".." {
+                bail!("Template rendered an invalid path for {:?}: {:?}", episode.filename, rendered);
+            }
+            path.push(component);
+        }
+        if path.as_os_str().is_empty() {
+            bail!("Template rendered an empty path for {:?}", episode.filename);
+        }
+
+        Ok(path)
+    }
+
+    /// Folder holding the whole series, the rendered path down to the first folder named with `{series}`,
+    /// e.g. `Series/Show` for the default layout.
+    pub fn series_dir(&self, episode: &Episode) -> Result<PathBuf> {
+        let is_series = |token: &Token| matches!(token, Token::Field { name, .. } if name == "series");
+        let series_at = self.tokens.iter().position(|token| match token {
+            Token::Optional(section) => section.iter().any(is_series),
+            token => is_series(token),
+        });
+
+        let path = self.render(episode)?;
+        if let Some(series_at) = series_at {
+            let before = Self::render_sections(&self.tokens[..series_at], episode);
+            let folders = before.split('/').rev().skip(1).filter(|folder| !folder.trim().is_empty()).count();
+            if folders + 1 < path.components().count() {
+                return Ok(path.components().take(folders + 1).collect());
+            }
+        }
+
+        bail!("Template has no series folder to move {:?} to", episode.filename)
+    }
+
+    /// Renders `tokens`, leaving out the optional sections with a field without value.
+    fn render_sections(tokens: &[Token], episode: &Episode) -> String {
+        let mut rendered = String::new();
+        for token in tokens {
+            match token {
+                Token::Optional(section) => {
+                    if let Some(section) = Self::render_tokens(section, episode) {
+                        rendered.push_str(&section);
+                    }
+                }
+                token => {
+                    let value = Self::render_tokens(std::slice::from_ref(token), episode);
+                    rendered.push_str(&value.unwrap_or_default());
+                }
+            }
+        }
+        rendered
+    }
+
+    /// Renders `tokens`, `None` as soon as one field has no value.
+    fn render_tokens(tokens: &[Token], episode: &Episode) -> Option<String> {
+        let mut rendered = String::new();
+        for token in tokens {
+            match token {
+                Token::Text(text) => rendered.push_str(text),
+                Token::Field { name, width } => rendered.push_str(&Self::field(name, *width, episode)?),
+                Token::Optional(_) => unreachable!("optional sections can't be nested"),
+            }
+        }
+        Some(rendered)
+    }
+
+    fn field(name: &str, width: usize, episode: &Episode) -> Option<String> {
+        let number = |value: u32| format!("{:0width$}", value, width = width);
+        let text = |value: &str| Some(sanitize_name(value)).filter(|value| !value.is_empty());
+
+        match name {
+            "series" => text(&episode.name),
+            "title" => text(episode.title.as_deref()?),
+            "year" => text(episode.year.as_deref()?),
+            "ext" => text(&episode.extension),
+            "resolution" => text(&format!("{}p", episode.tags.resolution?)),
+            "source" => text(&episode.tags.source?.to_string()),
+            "video_codec" => text(&episode.tags.video_codec?.to_string()),
+            "audio_codec" => text(&episode.tags.audio_codec?.to_string()),
+            "languages" => text(&episode.tags.languages.join(" ")),
+            "group" => text(episode.tags.group.as_deref()?),
+            "edition" => text(episode.tags.edition.as_deref()?),
+            "season" => Some(number(episode.season)),
+            "episode" => Some(number(episode.episode)),
+            "last_episode" => episode.last_episode.map(number),
+            "absolute" => episode.absolute_episode.map(number),
+            _ => None,
+        }
+    }
+}
+
+/// Strips the characters that can't be used in a folder or file name.
+pub fn sanitize_name(name: &str) -> String {
+    #[cfg(target_os = "windows")]
+    const RESERVED: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+    #[cfg(not(target_os = "windows"))]
+    const RESERVED: &[char] = &['/'];
+
+    name.replace(RESERVED, " ").split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::episode::MovieScore;
+    use crate::release::ReleaseTags;
+    use crate::rules::Rules;
+
+    fn episode(name: &str, season: u32, episode: u32) -> Episode {
+        Episode {
+            full_path: PathBuf::from("/in/file.mkv"),
+            filename: "file.mkv".to_string(),
+            filename_clean: "file mkv".to_string(),
+            extension: "mkv".to_string(),
+            name: name.to_string(),
+            year: None,
+            title: None,
+            season,
+            episode,
+            last_episode: None,
+            absolute_episode: None,
+            tags: ReleaseTags::default(),
+            rule: None,
+            is_movie: false,
+            movie: MovieScore::default(),
+        }
+    }
+
+    #[test]
+    fn default_templates_keep_the_historical_layout() {
+        let series = Template::parse(DEFAULT_SERIES_TEMPLATE).unwrap();
+        let movies = Template::parse(DEFAULT_MOVIE_TEMPLATE).unwrap();
+
+        let mut show = episode("Blazing Fast", 2, 5);
+        assert_eq!(series.render(&show).unwrap(), PathBuf::from("Series/Blazing Fast/S02/Blazing Fast - E05.mkv"));
+
+        show.year = Some("2021".to_string());
+        show.last_episode = Some(6);
+        assert_eq!(
+            series.render(&show).unwrap(),
+            PathBuf::from("Series/Blazing Fast (2021)/S02/Blazing Fast (2021) - E05-E06.mkv")
+        );
+
+        let mut movie = episode("Blade Runner", 0, 0);
+        movie.is_movie = true;
+        assert_eq!(movies.render(&movie).unwrap(), PathBuf::from("Films/Blade Runner.mkv"));
+    }
+
+    #[test]
+    fn optional_sections_and_sanitized_values() {
+        let template = Template::parse("{series}/Season {season}/{series} S{season:02}E{episode:02}[ - {title}].{ext}").unwrap();
+
+        let mut show = episode("AC/DC Live", 1, 3);
+        assert_eq!(template.render(&show).unwrap(), PathBuf::from("AC DC Live/Season 1/AC DC Live S01E03.mkv"));
+
+        show.title = Some("The Pilot".to_string());
+        assert_eq!(template.render(&show).unwrap(), PathBuf::from("AC DC Live/Season 1/AC DC Live S01E03 - The Pilot.mkv"));
+
+        let template = Template::parse("{series}[ - {edition}][ {resolution}][ {source}].{ext}").unwrap();
+        show.tags.resolution = Some(2160);
+        show.tags.edition = Some("Extended".to_string());
+        assert_eq!(template.render(&show).unwrap(), PathBuf::from("AC DC Live - Extended 2160p.mkv"));
+    }
+
+    #[test]
+    fn layouts_follow_media_server_conventions() {
+        let mut show = episode("Blazing Fast", 0, 2);
+        show.year = Some("2021".to_string());
+        let mut movie = episode("Blade Runner", 0, 0);
+        movie.year = Some("1982".to_string());
+        movie.is_movie = true;
+
+        let render = |layout: Layout| {
+            let (series, movies) = layout.templates();
+            (
+                Template::parse(series).unwrap().render(&show).unwrap(),
+                Template::parse(movies).unwrap().render(&movie).unwrap(),
+            )
+        };
+
+        assert_eq!(
+            render(Layout::Plex),
+            (
+                PathBuf::from("TV Shows/Blazing Fast (2021)/Season 00/Blazing Fast (2021) - s00e02.mkv"),
+                PathBuf::from("Movies/Blade Runner (1982)/Blade Runner (1982).mkv"),
+            )
+        );
+        assert_eq!(render(Layout::Jellyfin).0, PathBuf::from("Shows/Blazing Fast (2021)/Season 00/Blazing Fast S00E02.mkv"));
+        assert_eq!(render(Layout::Kodi).0, PathBuf::from("TV Shows/Blazing Fast (2021)/Season 0/Blazing Fast S00E02.mkv"));
+        assert_eq!(render(Layout::Mediasort).1, PathBuf::from("Films/Blade Runner (1982).mkv"));
+    }
+
+    #[test]
+    fn layout_names_are_parsed_back() {
+        let mut show = episode("Blazing Fast", 2, 5);
+        show.year = Some("2021".to_string());
+        show.last_episode = Some(6);
+
+        for layout in [Layout::Mediasort, Layout::Plex, Layout::Jellyfin, Layout::Kodi] {
+            let path = Template::parse(layout.templates().0).unwrap().render(&show).unwrap();
+            let parsed = Episode::from_name(path.file_name().unwrap().to_str().unwrap(), &Rules::default());
+
+            assert_eq!((parsed.name.as_str(), parsed.episode, parsed.last_episode), ("Blazing Fast", 5, Some(6)), "{:?}", path);
+            // The default layout only names the season in the folder
+            let season = if layout == Layout::Mediasort { 1 } else { 2 };
+            assert_eq!(parsed.season, season, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn series_folders_stop_at_the_series_name() {
+        let mut show = episode("Blazing Fast", 0, 2);
+        let series_dir = |template: &str, show: &Episode| Template::parse(template).unwrap().series_dir(show);
+
+        assert_eq!(series_dir(DEFAULT_SERIES_TEMPLATE, &show).unwrap(), PathBuf::from("Series/Blazing Fast"));
+        show.year = Some("2021".to_string());
+        assert_eq!(series_dir(Layout::Plex.templates().0, &show).unwrap(), PathBuf::from("TV Shows/Blazing Fast (2021)"));
+        assert_eq!(series_dir("[{year}/]{series}/{season}/{episode}.{ext}", &show).unwrap(), PathBuf::from("2021/Blazing Fast"));
+        assert!(series_dir("Series/{series} - E{episode:02}.{ext}", &show).is_err());
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        assert!(Template::parse("{series").is_err());
+        assert!(Template::parse("{series}]").is_err());
+        assert!(Template::parse("[{series}").is_err());
+        assert!(Template::parse("[[{series}]]").is_err());
+        assert!(Template::parse("{network}").is_err());
+        assert!(Template::parse("{series:02}").is_err());
+        assert!(Template::parse("{episode:xx}").is_err());
+        assert!(Template::parse("../{series}.{ext}").unwrap().render(&episode("Show", 1, 1)).is_err());
+    }
+}
@@ -0,0 +1,704 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+
+use crate::rules::Rules;
+
+/// Containers stripped from file names, release names have none.
+const EXTENSIONS: &[&str] = &["mkv", "mp4", "avi", "mov", "flv", "wmv", "webm", "m4v", "ts", "mpg"];
+
+/// Placeholder for a tag taken out of the name, it ends the episode title.
+const TAG: char = '\u{1}';
+
+static BRACKETS: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[([^\]]*)\]|\(([^)]*)\)").unwrap());
+/// `www.site.boats` anywhere, or `Site.tv` standing apart from the release name.
+static WEBSITE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)(?:^|\s)(?:www\.)?[a-z0-9-]+\.(?:com|net|org|info|tv|ws|fit|cc|co|vip|red|boats|uno|ec|to)(?:\s|$)|\bwww\.[a-z0-9-]+\.[a-z]{2,6}\b").unwrap()
+});
+static RESOLUTION: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)\b(?:(\d{3,4})[pi]|(4k|uhd))\b").unwrap());
+static SOURCE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(web[-. ]?dl|web[-. ]?rip|web|blu[-. ]?ray|bdrip|brrip|hdtv|dvdrip|dvd)\b").unwrap()
+});
+static VIDEO_CODEC: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)\b([xh]\.?26[45]|hevc|avc|av1|vp9|xvid|divx)\b").unwrap());
+/// Codec with its channels, e.g. `DDP5.1` or `AAC 2.0`.
+static AUDIO_CODEC: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(dts[-. ]?hd(?:[-. ]?ma)?|dts|truehd|e-?ac3|ddp|dd\+?|ac3|aac|flac|opus|mp3)(?:[ .]?[1-7]\.[01])?\b").unwrap()
+});
+static LANGUAGE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(vostfr|vosta|vost|truefrench|subfrench|vff|vfq|vfi|vf2|subbed|dubbed)\b").unwrap()
+});
+/// Also common words, only tags when upper case, `FRENCH` but not `The French Connection`.
+static LANGUAGE_UPPER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\b(MULTI|FRENCH|VF|VO|ENG|ENGLISH|JAP|JPN|GERMAN|ITA|SPA|KOREAN)\b").unwrap()
+});
+static EDITION: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(extended(?:[ .](?:cut|edition))?|director'?s[ .]cut|unrated|uncut|remastered|theatrical(?:[ .]cut)?|final[ .]cut|imax|criterion|special[ .]edition)\b").unwrap()
+});
+/// Tags dropped without being kept.
+static NOISE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(proper|repack|rerip|internal|limited|10[ .-]?bits?|8[ .-]?bits?|hdr10\+?|hdr|dovi|sdr|amzn|dsnp|hmax|atvp|remux|atmos)\b|\b(NF|DV|CR|ADN)\b").unwrap()
+});
+/// Fansub groups and download sites found outside of brackets.
+static KNOWN_GROUPS: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)\b(tsundere[ .]?raws|nandesuka|fansub|wawacity|vostfree)\b").unwrap());
+static YEAR: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(19\d{2}|20\d{2})\b").unwrap());
+/// `-GROUP` ending a release name, only after a tag so `Spider-Man` keeps its name.
+static RELEASE_GROUP: Lazy<Regex> = Lazy::new(|| Regex::new(r"-([A-Za-z0-9]+)\s*$").unwrap());
+
+/// Fansub releases numbered from the first episode of the show, e.g. `[Group] Show - 137 [1080p].mkv`.
+static FANSUB_EPISODE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?:\[[^\]]*\]\s*)*(?P<name>.+?)\s+-\s+(?P<episode>\d{1,4})(?:v\d+)?(?:[\s\[(]|\.[A-Za-z0-9]+$|$)").unwrap()
+});
+/// Multi-episode releases, e.g. `S02E05E06`, `S01E01-E03`, `S01E01-03` or `E05-E06` as the default layout names them.
+static MULTI_EPISODE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(?:S\d{1,2})?E(?P<first>\d{1,3})(?P<rest>(?:[-_. ]?E\d{1,3})+|-\d{1,3}\b)").unwrap()
+});
+pub(crate) static SEASON_EPISODE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)\bS\d{1,2}E\d{1,3}(?:[-_. ]?E?\d{1,3}\b)*").unwrap());
+static SEASON_EPISODE_TOKEN: Lazy<Regex> = Lazy::new(|| Regex::new(r"^S\d{1,2}E\d{1,3}").unwrap());
+static EPISODE_MARKER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\bE\d{1,3}\b").unwrap());
+/// Episode number standing alone, up to 4 digits but not a year, so `Blade Runner 2049` keeps its title.
+const EPISODE_NUMBER: &str = r"\d{1,3}|0\d{3}|1[0-8]\d\d|2[1-9]\d\d|[3-9]\d{3}";
+static NAME_PATTERNS: Lazy<Vec<Regex>> = Lazy::new(|| {
+    [
+        r"(.+?)(S\d{1,2}E\d{1,3}|S\d{1,2})".to_string(),
+        r"(.+?)(S\d{1,2} \d{1,2})".to_string(),
+        r"(.+?)(E\d{1,3})".to_string(),
+        format!(r"(.+?)\s({})\b", EPISODE_NUMBER),
+        r"(.+?)(Film|Movie)".to_string(),
+        r"(.+)".to_string(),
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).unwrap())
+    .collect()
+});
+static SEASON: Lazy<Regex> = Lazy::new(|| Regex::new(r"S(\d{1,2})(?:E\d{1,3})?").unwrap());
+static EPISODE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(&format!(r"(?:S\d{{1,2}}E(\d{{1,3}}))|(?:\bE(\d{{1,4}}))|(?:\b({})\b)", EPISODE_NUMBER)).unwrap()
+});
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Source {
+    #[serde(rename = "WEB-DL")]
+    WebDl,
+    #[serde(rename = "WEBRip")]
+    WebRip,
+    #[serde(rename = "WEB")]
+    Web,
+    #[serde(rename = "BluRay")]
+    BluRay,
+    #[serde(rename = "HDTV")]
+    Hdtv,
+    #[serde(rename = "DVD")]
+    Dvd,
+}
+
+impl Source {
+    fn from_tag(tag: &str) -> Source {
+        let tag = tag.to_lowercase().replace(['-', '.', ' '], "");
+        match tag.as_str() {
+            "webdl" => Source::WebDl,
+            "webrip" => Source::WebRip,
+            "web" => Source::Web,
+            "hdtv" => Source::Hdtv,
+            "dvd" | "dvdrip" => Source::Dvd,
+            _ => Source::BluRay,
+        }
+    }
+
+    /// Discs are encoded from the best master, web releases come straight from the stream.
+    pub fn rank(&self) -> u8 {
+        match self {
+            Source::BluRay => 5,
+            Source::WebDl => 4,
+            Source::Web => 3,
+            Source::WebRip => 2,
+            Source::Hdtv => 1,
+            Source::Dvd => 0,
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Source::WebDl => "WEB-DL",
+            Source::WebRip => "WEBRip",
+            Source::Web => "WEB",
+            Source::BluRay => "BluRay",
+            Source::Hdtv => "HDTV",
+            Source::Dvd => "DVD",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum VideoCodec {
+    #[serde(rename = "H.264")]
+    H264,
+    #[serde(rename = "H.265")]
+    H265,
+    #[serde(rename = "AV1")]
+    Av1,
+    #[serde(rename = "VP9")]
+    Vp9,
+    #[serde(rename = "XviD")]
+    Xvid,
+}
+
+impl VideoCodec {
+    fn from_tag(tag: &str) -> VideoCodec {
+        let tag = tag.to_lowercase().replace('.', "");
+        match tag.as_str() {
+            "x265" | "h265" | "hevc" => VideoCodec::H265,
+            "av1" => VideoCodec::Av1,
+            "vp9" => VideoCodec::Vp9,
+            "xvid" | "divx" => VideoCodec::Xvid,
+            _ => VideoCodec::H264,
+        }
+    }
+}
+
+impl fmt::Display for VideoCodec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            VideoCodec::H264 => "H.264",
+            VideoCodec::H265 => "H.265",
+            VideoCodec::Av1 => "AV1",
+            VideoCodec::Vp9 => "VP9",
+            VideoCodec::Xvid => "XviD",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum AudioCodec {
+    #[serde(rename = "AAC")]
+    Aac,
+    #[serde(rename = "AC3")]
+    Ac3,
+    #[serde(rename = "EAC3")]
+    Eac3,
+    #[serde(rename = "DTS")]
+    Dts,
+    #[serde(rename = "DTS-HD")]
+    DtsHd,
+    #[serde(rename = "TrueHD")]
+    TrueHd,
+    #[serde(rename = "FLAC")]
+    Flac,
+    #[serde(rename = "Opus")]
+    Opus,
+    #[serde(rename = "MP3")]
+    Mp3,
+}
+
+impl AudioCodec {
+    fn from_tag(tag: &str) -> AudioCodec {
+        let tag = tag.to_lowercase().replace(['-', '.', ' '], "");
+        match tag.as_str() {
+            "aac" => AudioCodec::Aac,
+            "ac3" | "dd" => AudioCodec::Ac3,
+            "eac3" | "ddp" | "dd+" => AudioCodec::Eac3,
+            "dts" => AudioCodec::Dts,
+            "truehd" => AudioCodec::TrueHd,
+            "flac" => AudioCodec::Flac,
+            "opus" => AudioCodec::Opus,
+            "mp3" => AudioCodec::Mp3,
+            _ => AudioCodec::DtsHd,
+        }
+    }
+}
+
+impl fmt::Display for AudioCodec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            AudioCodec::Aac => "AAC",
+            AudioCodec::Ac3 => "AC3",
+            AudioCodec::Eac3 => "EAC3",
+            AudioCodec::Dts => "DTS",
+            AudioCodec::DtsHd => "DTS-HD",
+            AudioCodec::TrueHd => "TrueHD",
+            AudioCodec::Flac => "FLAC",
+            AudioCodec::Opus => "Opus",
+            AudioCodec::Mp3 => "MP3",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Quality, language and release tags of a release name.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ReleaseTags {
+    /// Vertical resolution, 2160 for `4K`.
+    pub resolution: Option<u32>,
+    pub source: Option<Source>,
+    pub video_codec: Option<VideoCodec>,
+    pub audio_codec: Option<AudioCodec>,
+    /// Language and subtitle tags, upper case, e.g. `VOSTFR` or `MULTI`.
+    pub languages: Vec<String>,
+    pub group: Option<String>,
+    /// e.g. `Extended`, `Director's Cut` or `IMAX`.
+    pub edition: Option<String>,
+}
+
+/// A release name split into typed fields, e.g. `Show.S01E02.Pilot.1080p.WEB-DL.DDP5.1.x264-GROUP.mkv`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Release {
+    /// Series or movie name.
+    pub title: String,
+    pub year: Option<u32>,
+    /// 0 when the name has none.
+    pub season: u32,
+    /// 0 when the name has none.
+    pub episode: u32,
+    /// Last episode of a multi-episode release, e.g. 6 for `S02E05E06`.
+    pub last_episode: Option<u32>,
+    /// Episode number counted from the start of the show, when the release has no season.
+    pub absolute_episode: Option<u32>,
+    /// e.g. `Pilot` for `Show.S01E01.Pilot.720p.mkv`.
+    pub episode_title: Option<String>,
+    pub tags: ReleaseTags,
+    /// What is left of the name without its tags, separators replaced by spaces.
+    pub clean: String,
+    /// User rule the name matched, `None` when only the built-in parser was used.
+    pub rule: Option<String>,
+}
+
+fn current_year() -> u32 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    1970 + (now / 31_556_952) as u32
+}
+
+fn strip_extension(name: &str) -> &str {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if EXTENSIONS.contains(&extension.to_lowercase().as_str()) => stem,
+        _ => name,
+    }
+}
+
+/// Separators replaced by spaces, `Show.Name-Part_2` becomes `Show Name Part 2`.
+fn normalize(text: &str) -> String {
+    text.replace(['.', '_', '-', '+', TAG], " ").split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Replaces every match of `regex` by a tag placeholder, calling `found` on each.
+fn take(regex: &Regex, text: &str, found: impl FnMut(&Captures)) -> String {
+    take_after(regex, text, 0, found)
+}
+
+/// Like [`take`], but a match starting before `title_end` is only a tag in upper case:
+/// source, edition and noise tags are also title words, `Charlottes.Web.2006` keeps its `Web`.
+fn take_after(regex: &Regex, text: &str, title_end: usize, mut found: impl FnMut(&Captures)) -> String {
+    regex
+        .replace_all(text, |captures: &Captures| {
+            let tag = captures.get(0).unwrap();
+            if tag.start() < title_end && tag.as_str() != tag.as_str().to_uppercase() {
+                return tag.as_str().to_string();
+            }
+            found(captures);
+            format!(" {} ", TAG)
+        })
+        .into_owned()
+}
+
+/// Where the title ends: at the first tag, season and episode numbers, or year after some title.
+fn title_end(text: &str) -> usize {
+    let tag = text.find(TAG);
+    let season_episode = SEASON_EPISODE.find(text).or(EPISODE_MARKER.find(text)).map(|found| found.start());
+    let year = YEAR
+        .find_iter(text)
+        .find(|found| text[..found.start()].chars().any(char::is_alphanumeric))
+        .map(|found| found.start());
+
+    [tag, season_episode, year].into_iter().flatten().min().unwrap_or(text.len())
+}
+
+/// `director's.cut` becomes `Director's Cut`.
+fn edition_name(tag: &str) -> String {
+    tag.replace('.', " ")
+        .split_whitespace()
+        .map(|word| {
+            let word = word.to_lowercase();
+            let word = if word == "directors" { "director's".to_string() } else { word };
+            match word.as_str() {
+                "imax" => "IMAX".to_string(),
+                _ => word[..1].to_uppercase() + &word[1..],
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Takes the tags out of `text`, leaving placeholders. Without `has_title`, e.g. inside brackets,
+/// every word can be a tag.
+fn take_tags(text: &str, tags: &mut ReleaseTags, has_title: bool) -> String {
+    let title_end = |text: &str| if has_title { title_end(text) } else { 0 };
+
+    let text = take(&RESOLUTION, text, |captures| {
+        let height = captures.get(1).and_then(|height| height.as_str().parse().ok()).unwrap_or(2160);
+        tags.resolution.get_or_insert(height);
+    });
+    let text = take_after(&SOURCE, &text, title_end(&text), |captures| {
+        tags.source.get_or_insert(Source::from_tag(&captures[1]));
+    });
+    let text = take(&VIDEO_CODEC, &text, |captures| {
+        tags.video_codec.get_or_insert(VideoCodec::from_tag(&captures[1]));
+    });
+    let text = take(&AUDIO_CODEC, &text, |captures| {
+        tags.audio_codec.get_or_insert(AudioCodec::from_tag(&captures[1]));
+    });
+    let mut language = |captures: &Captures| {
+        let language = captures[1].to_uppercase();
+        if !tags.languages.contains(&language) {
+            tags.languages.push(language);
+        }
+    };
+    let text = take(&LANGUAGE, &text, &mut language);
+    let text = take(&LANGUAGE_UPPER, &text, &mut language);
+    let text = take_after(&EDITION, &text, title_end(&text), |captures| {
+        tags.edition.get_or_insert(edition_name(&captures[1]));
+    });
+    let text = take_after(&KNOWN_GROUPS, &text, title_end(&text), |captures| {
+        tags.group.get_or_insert(captures[1].to_string());
+    });
+
+    take_after(&NOISE, &text, title_end(&text), |_| {})
+}
+
+impl Release {
+    /// Parses `name` with the user rules first: tokens to strip, then the first matching pattern
+    /// overrides what its named groups capture.
+    pub fn parse_with(name: &str, rules: &Rules) -> Release {
+        let name = rules.strip(name);
+        let mut release = Release::parse(&name);
+
+        for rule in &rules.patterns {
+            let Some(captures) = rule.captures(strip_extension(&name)) else { continue };
+            let number = |group: &str| captures.name(group).and_then(|value| value.as_str().parse::<u32>().ok());
+
+            if let Some(series) = captures.name("series") {
+                release.title = normalize(series.as_str());
+            }
+            if let Some(year) = number("year") {
+                release.year = Some(year);
+            }
+            if let Some(episode) = number("episode") {
+                release.episode = episode;
+                release.last_episode = release.last_episode.filter(|last| *last > episode);
+                if release.absolute_episode.is_some() {
+                    release.absolute_episode = Some(episode);
+                }
+            }
+            if let Some(season) = number("season") {
+                release.season = season;
+                release.absolute_episode = None;
+            }
+            release.rule = Some(rule.name.clone());
+            break;
+        }
+
+        release
+    }
+
+    /// Parses `name` with the built-in rules alone.
+    pub fn parse(name: &str) -> Release {
+        let stem = strip_extension(name);
+        let mut tags = ReleaseTags::default();
+        let mut year = None;
+
+        // Bracketed parts are never part of the title: a leading `[Group]`, `(2019)` or tags like `[1080p]`
+        for captures in BRACKETS.captures_iter(stem) {
+            let content = captures.get(1).or(captures.get(2)).unwrap().as_str().trim();
+            if YEAR.is_match(content) && content.len() == 4 {
+                year = content.parse().ok();
+            } else if captures.get(0).unwrap().start() == 0 && captures.get(1).is_some() {
+                tags.group = Some(content.to_string()).filter(|group| !group.is_empty());
+            } else {
+                take_tags(&content.replace('_', " "), &mut tags, false);
+            }
+        }
+
+        let text = BRACKETS.replace_all(stem, " ").replace('_', " ");
+        let text = WEBSITE.replace_all(&text, " ").into_owned();
+        let mut text = take_tags(&text, &mut tags, true);
+
+        if let Some(captures) = RELEASE_GROUP.captures(&text) {
+            let start = captures.get(0).unwrap().start();
+            if text[..start].contains(TAG) {
+                tags.group.get_or_insert(captures[1].to_string());
+                text.truncate(start);
+            }
+        }
+
+        // A year after the title and before the tags, `2049` in `Blade.Runner.2049` is not a year yet
+        if year.is_none() {
+            let tags_start = text.find(TAG).unwrap_or(text.len());
+            let found = YEAR
+                .find_iter(&text[..tags_start])
+                .filter(|found| text[..found.start()].chars().any(char::is_alphanumeric))
+                .filter(|found| found.as_str().parse::<u32>().is_ok_and(|year| year <= current_year() + 1))
+                .last();
+            if let Some(found) = found {
+                year = found.as_str().parse().ok();
+                text.replace_range(found.range(), &format!(" {} ", TAG));
+            }
+        }
+
+        // `s01e01` as `S01E01`, the season and episode tokens are upper case
+        let text = SEASON_EPISODE.replace_all(&text, |captures: &Captures| captures[0].to_uppercase()).into_owned();
+
+        let episode_title = SEASON_EPISODE.find(&text).and_then(|found| {
+            let title = normalize(text[found.end()..].split(TAG).next()?);
+            (!title.is_empty()).then_some(title)
+        });
+
+        let mut release = Release {
+            year,
+            episode_title,
+            tags,
+            clean: normalize(&text),
+            ..Release::default()
+        };
+        release.parse_numbers(name);
+
+        release
+    }
+
+    /// Title, season and episode numbers, from the clean name.
+    fn parse_numbers(&mut self, name: &str) {
+        self.title = self.extract_title().unwrap_or_default();
+        self.season = self.extract_season();
+        self.episode = self.extract_episode();
+        if let Some((first, last)) = Self::extract_episode_range(name) {
+            self.episode = first;
+            self.last_episode = Some(last);
+        }
+        if let Some((title, absolute_episode)) = self.extract_absolute_episode(name) {
+            // Until metadata maps it to a season, the whole show is one season
+            self.title = title;
+            self.season = 1;
+            self.episode = absolute_episode;
+            self.absolute_episode = Some(absolute_episode);
+        } else if self.bare_number(name).is_some() {
+            // A short number alone is part of the title, `Toy Story 3` is no episode
+            self.title = self.clean.clone();
+            self.episode = 0;
+        }
+    }
+
+    fn extract_title(&self) -> Option<String> {
+        //use first string operation if possible to avoid regex
+        let name: Vec<&str> = self.clean.split_whitespace().collect();
+
+        let is_marker = |token: &str| {
+            let is_number = |prefix: char| token.starts_with(prefix) && token.len() > 1 && token.chars().skip(1).all(char::is_numeric);
+            is_number('S') || is_number('E') || SEASON_EPISODE_TOKEN.is_match(token)
+        };
+        if let Some(i) = name.iter().position(|token| is_marker(token)) {
+            return Some(name[..i].join(" ").trim().to_string());
+        }
+
+        NAME_PATTERNS
+            .iter()
+            .find_map(|pattern| Some(pattern.captures(&self.clean)?.get(1)?.as_str().trim().to_string()))
+    }
+
+    fn extract_season(&self) -> u32 {
+        //use first string operation if possible to avoid regex
+        for token in self.clean.split_whitespace() {
+            if token.starts_with('S') && token.len() > 1 && token.chars().skip(1).all(char::is_numeric) {
+                return token[1..].parse::<u32>().unwrap_or(1);
+            }
+        }
+
+        SEASON
+            .captures(&self.clean)
+            .and_then(|captures| captures.get(1))
+            .map(|season| season.as_str().parse::<u32>().unwrap_or(1))
+            .unwrap_or(0)
+    }
+
+    fn extract_episode(&self) -> u32 {
+        //use first string operation if possible to avoid regex
+        for token in self.clean.split_whitespace() {
+            if token.starts_with('E') && token.len() > 1 && token.chars().skip(1).all(char::is_numeric) {
+                return token[1..].parse::<u32>().unwrap_or(1);
+            }
+        }
+
+        // `S01E01`, then `E01`, then a bare number, wherever they are: `24.S01E01` is episode 1
+        let episodes: Vec<Captures> = EPISODE.captures_iter(&self.clean).collect();
+        (1..=3)
+            .find_map(|group| episodes.iter().find_map(|captures| captures.get(group)))
+            .map(|episode| episode.as_str().parse::<u32>().unwrap_or(1))
+            .unwrap_or(0)
+    }
+
+    /// First and last episode of a multi-episode release, from the raw name as cleaning drops the `-`.
+    fn extract_episode_range(name: &str) -> Option<(u32, u32)> {
+        let captures = MULTI_EPISODE.captures(name)?;
+        let first = captures["first"].parse::<u32>().ok()?;
+        let last = captures["rest"]
+            .rsplit(|c: char| !c.is_ascii_digit())
+            .next()?
+            .parse::<u32>()
+            .ok()?;
+
+        (last > first).then_some((first, last))
+    }
+
+    /// Whether the release has neither a season nor an episode marker.
+    fn is_unnumbered(&self, name: &str) -> bool {
+        // Season 0 is a parsed season when the release has an explicit `S00E01`
+        self.season == 0 && !EPISODE_MARKER.is_match(&self.clean) && !SEASON_EPISODE.is_match(name)
+    }
+
+    /// Number standing alone in a release without season or episode markers, e.g. `366` in `Bleach 366`.
+    fn bare_number(&self, name: &str) -> Option<&str> {
+        if !self.is_unnumbered(name) {
+            return None;
+        }
+
+        Some(EPISODE.captures(&self.clean)?.get(3)?.as_str())
+    }
+
+    /// Episode counted from the start of the show: an episode marker without a season like `Show - E500`,
+    /// the fansub `Show - 137` shape, or a bare number that can't be a sequel, three digits or more
+    /// or zero-padded like `Bleach 366` or `Show 05`.
+    fn extract_absolute_episode(&self, name: &str) -> Option<(String, u32)> {
+        let has_episode_marker = EPISODE_MARKER.is_match(&self.clean) || self.last_episode.is_some();
+        if self.season == 0 && !SEASON_EPISODE.is_match(name) && has_episode_marker {
+            return (self.episode > 0).then(|| (self.title.clone(), self.episode));
+        }
+        if !self.is_unnumbered(name) {
+            return None;
+        }
+
+        if let Some(captures) = FANSUB_EPISODE.captures(name) {
+            let title = Release::parse(&captures["name"]).clean;
+            if let Ok(episode) = captures["episode"].parse::<u32>() {
+                if !title.is_empty() && episode > 0 {
+                    return Some((title, episode));
+                }
+            }
+        }
+
+        let number = self.bare_number(name)?;
+        let is_episode = number.len() >= 3 || number.starts_with('0');
+        (is_episode && self.episode > 0).then(|| (self.title.clone(), self.episode))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scene_release_tags_are_typed() {
+        let release = Release::parse("Show.Name.S01E02.The.Pilot.1080p.WEB-DL.DDP5.1.H.264-GROUP.mkv");
+
+        assert_eq!(release.title, "Show Name");
+        assert_eq!((release.season, release.episode), (1, 2));
+        assert_eq!(release.episode_title.as_deref(), Some("The Pilot"));
+        assert_eq!(release.tags.resolution, Some(1080));
+        assert_eq!(release.tags.source, Some(Source::WebDl));
+        assert_eq!(release.tags.video_codec, Some(VideoCodec::H264));
+        assert_eq!(release.tags.audio_codec, Some(AudioCodec::Eac3));
+        assert_eq!(release.tags.group.as_deref(), Some("GROUP"));
+
+        let release = Release::parse("show.name.s03e112.the.finale.720p.mkv");
+        assert_eq!(release.title, "show name");
+        assert_eq!((release.season, release.episode), (3, 112));
+        assert_eq!(release.episode_title.as_deref(), Some("the finale"));
+    }
+
+    #[test]
+    fn french_releases_keep_their_language_tags() {
+        let release = Release::parse("Show.S02E05E06.MULTI.VOSTFR.2160p.BluRay.x265.TrueHD.7.1-Team.mkv");
+
+        assert_eq!(release.title, "Show");
+        assert_eq!((release.season, release.episode, release.last_episode), (2, 5, Some(6)));
+        assert_eq!(release.episode_title, None);
+        assert_eq!(release.tags.languages, vec!["VOSTFR", "MULTI"]);
+        assert_eq!(release.tags.resolution, Some(2160));
+        assert_eq!(release.tags.source, Some(Source::BluRay));
+        assert_eq!(release.tags.video_codec, Some(VideoCodec::H265));
+        assert_eq!(release.tags.audio_codec, Some(AudioCodec::TrueHd));
+    }
+
+    #[test]
+    fn episode_ranges_need_a_later_last_episode() {
+        assert_eq!(Release::extract_episode_range("Show.S01E01E02.mkv"), Some((1, 2)));
+        assert_eq!(Release::extract_episode_range("Show.S01E01-E03.720p.mkv"), Some((1, 3)));
+        assert_eq!(Release::extract_episode_range("Show.S01E01-03.mkv"), Some((1, 3)));
+        assert_eq!(Release::extract_episode_range("Show.S01E01-1080p.mkv"), None);
+        assert_eq!(Release::extract_episode_range("Show.S01E03-E01.mkv"), None);
+        assert_eq!(Release::extract_episode_range("Show.S01E01.mkv"), None);
+        assert_eq!(Release::extract_episode_range("Show - E05-E06.mkv"), Some((5, 6)));
+        assert_eq!(Release::extract_episode_range("Show - E05E06.mkv"), Some((5, 6)));
+        assert_eq!(Release::extract_episode_range("Show - E05-1080p.mkv"), None);
+
+        let release = Release::parse("Show.S01E01-1080p.mkv");
+        assert_eq!((release.episode, release.last_episode), (1, None));
+    }
+
+    #[test]
+    fn fansub_releases_are_numbered_from_the_start() {
+        let release = Release::parse("[TsundereRaws] Show Name - 137 [1080p] [VOSTFR].mkv");
+
+        assert_eq!(release.title, "Show Name");
+        assert_eq!((release.season, release.episode, release.absolute_episode), (1, 137, Some(137)));
+        assert_eq!(release.tags.group.as_deref(), Some("TsundereRaws"));
+        assert_eq!(release.tags.resolution, Some(1080));
+        assert_eq!(release.tags.languages, vec!["VOSTFR"]);
+
+        // An episode marker without a season counts from the start too, an explicit `S00` is a special
+        let release = Release::parse("Naruto Shippuden - E500 VOSTFR.mp4");
+        assert_eq!((release.title.as_str(), release.season, release.episode, release.absolute_episode), ("Naruto Shippuden", 1, 500, Some(500)));
+        let release = Release::parse("Sherlock.S00E01.Unaired.Pilot.720p.BluRay.mkv");
+        assert_eq!((release.season, release.episode, release.absolute_episode), (0, 1, None));
+    }
+
+    #[test]
+    fn movies_keep_their_year_and_edition() {
+        let release = Release::parse("The.French.Connection.1971.Directors.Cut.REMASTERED.720p.BluRay.DTS-HD.MA.5.1.x264.mkv");
+
+        assert_eq!(release.title, "The French Connection");
+        assert_eq!(release.year, Some(1971));
+        assert_eq!(release.tags.edition.as_deref(), Some("Director's Cut"));
+        assert_eq!(release.tags.audio_codec, Some(AudioCodec::DtsHd));
+        assert!(release.tags.languages.is_empty());
+
+        let release = Release::parse("Blade.Runner.1982.Final.Cut.KOREAN.1080p.BluRay.mkv");
+        assert_eq!((release.title.as_str(), release.year), ("Blade Runner", Some(1982)));
+        assert_eq!(release.tags.edition.as_deref(), Some("Final Cut"));
+        assert_eq!(release.tags.languages, vec!["KOREAN"]);
+
+        let release = Release::parse("Uncut.Gems.2019.UNCUT.1080p.WEB-DL.mkv");
+        assert_eq!((release.title.as_str(), release.year), ("Uncut Gems", Some(2019)));
+        assert_eq!(release.tags.edition.as_deref(), Some("Uncut"));
+        assert_eq!(release.tags.source, Some(Source::WebDl));
+
+        let release = Release::parse("[Group] Show - 05 [Web 1080p].mkv");
+        assert_eq!(release.tags.source, Some(Source::Web));
+
+        let release = Release::parse("Blade Runner 2049 (2017).mkv");
+        assert_eq!(release.year, Some(2017));
+        assert_eq!(release.clean, "Blade Runner 2049");
+    }
+
+    #[test]
+    fn hyphenated_titles_are_not_release_groups() {
+        let release = Release::parse("Spider-Man.mkv");
+        assert_eq!(release.tags.group, None);
+        assert_eq!(release.clean, "Spider Man");
+
+        let release = Release::parse("www.Wawacity.boats - Show S01E03 VOSTFR.mp4");
+        assert_eq!(release.title, "Show");
+        assert_eq!(release.episode, 3);
+    }
+}
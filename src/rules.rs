@@ -0,0 +1,151 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use regex::{Captures, Regex};
+use serde::Deserialize;
+
+/// Named groups a rule can capture.
+const GROUPS: &[&str] = &["series", "season", "episode", "year"];
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    strip: Vec<String>,
+    #[serde(default)]
+    patterns: Vec<PatternFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PatternFile {
+    name: Option<String>,
+    regex: String,
+}
+
+/// A user regex tried on release names before the built-in parser.
+#[derive(Debug)]
+pub struct Rule {
+    pub name: String,
+    regex: Regex,
+}
+
+impl Rule {
+    pub fn captures<'a>(&self, name: &'a str) -> Option<Captures<'a>> {
+        self.regex.captures(name)
+    }
+}
+
+/// Parsing rules from a rules file: ordered extraction regexes and tokens stripped from release names, e.g.
+///
+/// ```json
+/// {
+///   "strip": ["NanDesuKa", "www.example.fit"],
+///   "patterns": [{ "name": "erai", "regex": "^\\[Erai-raws\\] (?P<series>.+) - (?P<episode>\\d+)" }]
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct Rules {
+    /// File the rules come from, `None` for the built-in parser alone.
+    pub path: Option<PathBuf>,
+    pub patterns: Vec<Rule>,
+    strip: Option<Regex>,
+}
+
+impl Rules {
+    /// Loads a rules file, a missing file has no rules.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Rules> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Rules::default());
+        }
+
+        let content = fs::read_to_string(path).with_context(|| format!("Could not read rules file {:?}", path))?;
+        let file: RulesFile = serde_json::from_str(&content).with_context(|| format!("Invalid rules file {:?}", path))?;
+
+        let mut rules = Rules::parse(file).with_context(|| format!("Invalid rules file {:?}", path))?;
+        rules.path = Some(path.to_path_buf());
+
+        Ok(rules)
+    }
+
+    fn parse(file: RulesFile) -> Result<Rules> {
+        let mut patterns = Vec::new();
+        for (i, pattern) in file.patterns.into_iter().enumerate() {
+            let name = pattern.name.unwrap_or_else(|| format!("#{}", i + 1));
+            let regex = Regex::new(&pattern.regex).with_context(|| format!("Invalid regex in rule {}", name))?;
+            if !regex.capture_names().flatten().any(|group| GROUPS.contains(&group)) {
+                bail!("Rule {} captures nothing, name a group {}", name, GROUPS.join(", "));
+            }
+            patterns.push(Rule { name, regex });
+        }
+
+        let tokens: Vec<String> = file
+            .strip
+            .iter()
+            .filter(|token| !token.trim().is_empty())
+            .map(|token| {
+                // `\b` only next to word characters, so `[Group]` can be stripped too
+                let token = token.trim();
+                let boundary = |c: Option<char>| if c.is_some_and(char::is_alphanumeric) { r"\b" } else { "" };
+                format!("{}{}{}", boundary(token.chars().next()), regex::escape(token), boundary(token.chars().last()))
+            })
+            .collect();
+        let strip = match tokens.is_empty() {
+            true => None,
+            false => Some(Regex::new(&format!("(?i){}", tokens.join("|")))?),
+        };
+
+        Ok(Rules { path: None, patterns, strip })
+    }
+
+    /// `name` without the tokens to strip.
+    pub fn strip(&self, name: &str) -> String {
+        match &self.strip {
+            Some(strip) => strip.replace_all(name, " ").into_owned(),
+            None => name.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::release::Release;
+
+    #[test]
+    fn rules_need_a_known_group() {
+        let file = |regex: &str| RulesFile {
+            strip: vec!["NanDesuKa".to_string(), "[HorribleSubs]".to_string()],
+            patterns: vec![PatternFile { name: Some("test".to_string()), regex: regex.to_string() }],
+        };
+
+        assert!(Rules::parse(file(r"^(?P<show>.+) - \d+")).is_err());
+        assert!(Rules::parse(file(r"^(?P<series>.+")).is_err());
+
+        let rules = Rules::parse(file(r"^(?P<series>.+) - (?P<episode>\d+)")).unwrap();
+        assert_eq!(rules.strip("[HorribleSubs] Show - 01 nandesuka").split_whitespace().collect::<Vec<&str>>(), vec!["Show", "-", "01"]);
+    }
+
+    #[test]
+    fn first_matching_rule_overrides_the_built_in_parser() {
+        let rules = Rules::parse(RulesFile {
+            strip: vec!["NanDesuKa".to_string()],
+            patterns: vec![
+                PatternFile { name: Some("daily".to_string()), regex: r"^(?P<series>.+?) (?P<year>\d{4})-\d\d-\d\d$".to_string() },
+                PatternFile { name: None, regex: r"^(?P<series>.+?)\.Ep(?P<episode>\d+)\.S(?P<season>\d+)".to_string() },
+            ],
+        })
+        .unwrap();
+
+        let release = Release::parse_with("NanDesuKa Show.Ep07.S02.1080p.mkv", &rules);
+        assert_eq!(release.rule.as_deref(), Some("#2"));
+        assert_eq!(release.title, "Show");
+        assert_eq!((release.season, release.episode, release.absolute_episode), (2, 7, None));
+        assert_eq!(release.tags.resolution, Some(1080));
+
+        let release = Release::parse_with("Show.S01E01.mkv", &rules);
+        assert_eq!((release.rule, release.title.as_str(), release.episode), (None, "Show", 1));
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+use super::result::*;
+use super::search::MetadataProvider;
+use super::strings::normalize_string;
+
+/// Default time to live of cached lookups, in hours.
+pub const DEFAULT_CACHE_TTL: u64 = 24 * 7;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CacheEntry<T> {
+    pub stored: u64,
+    pub value: T,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CacheFile {
+    #[serde(default)]
+    pub searches: HashMap<String, CacheEntry<Vec<MediaResult>>>,
+    #[serde(default)]
+    pub details: HashMap<String, CacheEntry<MediaDetails>>,
+}
+
+/// On-disk cache of metadata lookups, shared by every provider of a chain.
+pub struct MetadataCache {
+    path: PathBuf,
+    ttl: u64,
+    content: Mutex<CacheFile>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn search_key(provider: &str, query: &str, year: &str, media_type: &MediaType) -> String {
+    format!("{}|{}|{}|{}", provider, normalize_string(query), year, media_type)
+}
+
+pub fn details_key(provider: &str, id: &str, media_type: &MediaType) -> String {
+    format!("{}|{}|{}", provider, id, media_type)
+}
+
+impl MetadataCache {
+    /// Loads the cache file, a missing or unreadable file starts an empty cache.
+    pub fn load<P: AsRef<Path>>(path: P, ttl_hours: u64) -> MetadataCache {
+        let content = fs::read_to_string(path.as_ref())
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+
+        MetadataCache {
+            path: path.as_ref().to_path_buf(),
+            ttl: ttl_hours * 3600,
+            content: Mutex::new(content),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let content = self.content.lock().unwrap();
+        fs::write(&self.path, serde_json::to_string(&*content)?)?;
+        Ok(())
+    }
+
+    pub fn is_fresh<T>(&self, entry: &CacheEntry<T>) -> bool {
+        now().saturating_sub(entry.stored) <= self.ttl
+    }
+
+    /// Returns the cached results, expired ones only when `allow_expired` is set.
+    pub fn get_search(&self, key: &str, allow_expired: bool) -> Option<Vec<MediaResult>> {
+        let content = self.content.lock().unwrap();
+        let entry = content.searches.get(key)?;
+        (allow_expired || self.is_fresh(entry)).then(|| entry.value.clone())
+    }
+
+    pub fn set_search(&self, key: String, results: &[MediaResult]) {
+        self.content.lock().unwrap().searches.insert(key, CacheEntry { stored: now(), value: results.to_vec() });
+    }
+
+    pub fn get_details(&self, key: &str, allow_expired: bool) -> Option<MediaDetails> {
+        let content = self.content.lock().unwrap();
+        let entry = content.details.get(key)?;
+        (allow_expired || self.is_fresh(entry)).then(|| entry.value.clone())
+    }
+
+    pub fn set_details(&self, key: String, details: &MediaDetails) {
+        self.content.lock().unwrap().details.insert(key, CacheEntry { stored: now(), value: details.clone() });
+    }
+
+    /// Returns `(fresh, expired)` entry counts.
+    pub fn stats(&self) -> (usize, usize) {
+        let content = self.content.lock().unwrap();
+        let fresh = content.searches.values().filter(|entry| self.is_fresh(entry)).count()
+            + content.details.values().filter(|entry| self.is_fresh(entry)).count();
+        let total = content.searches.len() + content.details.len();
+
+        (fresh, total - fresh)
+    }
+}
+
+/// Wraps a provider so identical lookups only hit the API once per TTL.
+/// Offline, only cached data is used, even expired.
+pub struct CachedProvider {
+    inner: Box<dyn MetadataProvider>,
+    cache: Arc<MetadataCache>,
+    offline: bool,
+}
+
+impl CachedProvider {
+    pub fn new(inner: Box<dyn MetadataProvider>, cache: Arc<MetadataCache>, offline: bool) -> CachedProvider {
+        CachedProvider { inner, cache, offline }
+    }
+}
+
+impl MetadataProvider for CachedProvider {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn supports(&self, media_type: &MediaType) -> bool {
+        self.inner.supports(media_type)
+    }
+
+    fn search(&self, query: &str, year: &str, media_type: MediaType) -> Result<Vec<MediaResult>, Error> {
+        let key = search_key(self.name(), query, year, &media_type);
+
+        if let Some(results) = self.cache.get_search(&key, self.offline) {
+            return Ok(results);
+        }
+        if self.offline {
+            return Ok(Vec::new());
+        }
+
+        let results = self.inner.search(query, year, media_type)?;
+        self.cache.set_search(key, &results);
+
+        Ok(results)
+    }
+
+    fn details(&self, id: &str, media_type: MediaType) -> Result<MediaDetails, Error> {
+        let key = details_key(self.name(), id, &media_type);
+
+        if let Some(details) = self.cache.get_details(&key, self.offline) {
+            return Ok(details);
+        }
+        if self.offline {
+            return Err(Error::msg(format!("{} details for {} are not cached", self.name(), id)));
+        }
+
+        let details = self.inner.details(id, media_type)?;
+        self.cache.set_details(key, &details);
+
+        Ok(details)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch_dir::ScratchDir;
+    use crate::search::search_tvmaze::TvMaze;
+    use crate::search::test_server::TestServer;
+
+    #[test]
+    fn identical_queries_hit_the_api_once() {
+        let server = TestServer::start(|_| {
+            r#"[{"show": {"id": 1, "name": "Blazing Fast", "premiered": "2021-04-01"}}]"#.to_string()
+        });
+        let dir = ScratchDir::new("cache");
+        let path = dir.join("metadata.json");
+        let cache = Arc::new(MetadataCache::load(&path, DEFAULT_CACHE_TTL));
+        let provider = CachedProvider::new(Box::new(TvMaze::at(&server.url)), cache.clone(), false);
+
+        for query in ["Blazing Fast", "blazing.fast", "Blazing  Fast"] {
+            assert_eq!(provider.search(query, "", SERIES.clone()).unwrap()[0].title, "Blazing Fast");
+        }
+        assert_eq!(server.requests().len(), 1);
+
+        cache.save().unwrap();
+        let offline = CachedProvider::new(Box::new(TvMaze::at("http://127.0.0.1:9")), Arc::new(MetadataCache::load(&path, 0)), true);
+        assert_eq!(offline.search("Blazing Fast", "", SERIES.clone()).unwrap().len(), 1);
+        assert!(offline.search("Something Else", "", SERIES.clone()).unwrap().is_empty());
+    }
+}
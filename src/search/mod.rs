@@ -1,5 +1,12 @@
-mod search_tmdb;
+pub(crate) mod cache;
+pub(crate) mod search_anilist;
+pub(crate) mod search_tmdb;
 pub(crate) mod search_tvmaze;
 pub(crate) mod result;
-mod search;
-mod strings;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub(crate) mod search;
+// Ported from a Go matcher, not every pattern is used yet
+#[allow(dead_code)]
+mod strings;
+#[cfg(test)]
+pub(crate) mod test_server;
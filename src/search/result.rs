@@ -1,13 +1,16 @@
-
 pub type MediaType = String;
 
 
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 
 pub static SERIES: Lazy<MediaType> = Lazy::new(|| String::from("series"));
 pub static MOVIE: Lazy<MediaType> = Lazy::new(|| String::from("movie"));
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MediaResult{
+    /// Identifier in the backend that returned the result.
+    pub id: String,
     pub title: String,
     pub year: String,
     pub media_type: MediaType,
@@ -16,8 +19,9 @@ pub struct MediaResult{
 }
 
 impl MediaResult {
-    pub fn new(title: String, year: String, media_type: MediaType, is_duplicate: bool, accuracy: i64) -> MediaResult {
+    pub fn new(id: String, title: String, year: String, media_type: MediaType, is_duplicate: bool, accuracy: i64) -> MediaResult {
       MediaResult {
+            id,
             title,
             year,
             media_type,
@@ -29,4 +33,21 @@ impl MediaResult {
     pub fn string(&self) -> String {
         format!("{} ({})", self.title, self.year)
     }
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SeasonDetails {
+    pub number: u32,
+    pub episode_count: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MediaDetails {
+    pub id: String,
+    pub title: String,
+    pub year: String,
+    pub media_type: MediaType,
+    /// Runtime in minutes, per episode for series.
+    pub runtime: Option<u32>,
+    pub seasons: Vec<SeasonDetails>,
+}
@@ -0,0 +1,256 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+
+use super::cache::{CachedProvider, MetadataCache};
+use super::result::*;
+use super::search_anilist::AniList;
+use super::search_tmdb::Tmdb;
+use super::search_tvmaze::TvMaze;
+
+pub const TVMAZE: &str = "tvmaze";
+pub const TMDB: &str = "tmdb";
+pub const ANILIST: &str = "anilist";
+
+/// Minimum accuracy for a search result to be trusted over the parsed name.
+pub const DEFAULT_ACCURACY_THRESHOLD: i64 = 70;
+
+/// A metadata backend, searched by the `ProviderChain` in the configured order.
+pub trait MetadataProvider: Send + Sync {
+    /// Name used to configure the provider chain.
+    fn name(&self) -> &str;
+
+    fn supports(&self, media_type: &MediaType) -> bool;
+
+    fn search(&self, query: &str, year: &str, media_type: MediaType) -> Result<Vec<MediaResult>, Error>;
+
+    fn details(&self, id: &str, media_type: MediaType) -> Result<MediaDetails, Error>;
+}
+
+/// Returns the most accurate result, as long as it reaches `threshold`.
+pub fn best_match(results: Vec<MediaResult>, threshold: i64) -> Option<MediaResult> {
+    results
+        .into_iter()
+        .filter(|result| result.accuracy >= threshold)
+        .max_by_key(|result| result.accuracy)
+}
+
+/// Converts an absolute episode number to `(season, episode)` using the season episode counts.
+/// Specials (season 0) are not part of the absolute numbering.
+pub fn absolute_to_season(absolute_episode: u32, seasons: &[SeasonDetails]) -> Option<(u32, u32)> {
+    let mut regular_seasons: Vec<&SeasonDetails> = seasons
+        .iter()
+        .filter(|season| season.number > 0 && season.episode_count > 0)
+        .collect();
+    regular_seasons.sort_by_key(|season| season.number);
+
+    let mut remaining = absolute_episode;
+    for season in regular_seasons {
+        if remaining == 0 {
+            break;
+        }
+        if remaining <= season.episode_count {
+            return Some((season.number, remaining));
+        }
+        remaining -= season.episode_count;
+    }
+
+    None
+}
+
+/// Provider names used when none are configured, TMDB needs an API key.
+pub fn default_provider_names(has_tmdb: bool) -> Vec<String> {
+    let mut names = vec![TVMAZE.to_string()];
+    if has_tmdb {
+        names.push(TMDB.to_string());
+    }
+    names
+}
+
+/// Ordered fallback chain of metadata providers, e.g. `anilist,tmdb,tvmaze`.
+pub struct ProviderChain {
+    providers: Vec<Box<dyn MetadataProvider>>,
+}
+
+impl ProviderChain {
+    pub fn new(providers: Vec<Box<dyn MetadataProvider>>) -> ProviderChain {
+        ProviderChain { providers }
+    }
+
+    pub fn from_names(names: &[String], tmdb: Option<Tmdb>) -> Result<ProviderChain, Error> {
+        let mut providers: Vec<Box<dyn MetadataProvider>> = Vec::new();
+
+        for name in names {
+            match name.trim().to_lowercase().as_str() {
+                TVMAZE => providers.push(Box::new(TvMaze::new())),
+                TMDB => match &tmdb {
+                    Some(tmdb) => providers.push(Box::new(tmdb.clone())),
+                    None => bail!("The {} provider needs an API key", TMDB),
+                },
+                ANILIST => providers.push(Box::new(AniList::new())),
+                other => bail!("Unknown metadata provider: {}", other),
+            }
+        }
+
+        Ok(ProviderChain::new(providers))
+    }
+
+    /// Routes every provider through `cache`, offline only cached data is used.
+    pub fn with_cache(self, cache: Arc<MetadataCache>, offline: bool) -> ProviderChain {
+        let providers = self
+            .providers
+            .into_iter()
+            .map(|provider| Box::new(CachedProvider::new(provider, cache.clone(), offline)) as Box<dyn MetadataProvider>)
+            .collect();
+
+        ProviderChain { providers }
+    }
+
+    /// Asks every provider supporting `media_type` in order, the first match above `threshold` wins.
+    /// Fails only if every provider failed.
+    pub fn lookup(&self, name: &str, year: &str, media_type: MediaType, threshold: i64) -> Result<Option<(&dyn MetadataProvider, MediaResult)>, Error> {
+        let mut last_error: Option<Error> = None;
+        let mut answered = false;
+
+        for provider in self.providers.iter().filter(|provider| provider.supports(&media_type)) {
+            match provider.search(name, year, media_type.clone()) {
+                Ok(results) => {
+                    answered = true;
+                    if let Some(result) = best_match(results, threshold) {
+                        return Ok(Some((provider.as_ref(), result)));
+                    }
+                }
+                Err(e) => last_error = Some(e.context(format!("{} lookup failed", provider.name()))),
+            }
+        }
+
+        match last_error {
+            Some(e) if !answered => Err(e),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::test_server::TestServer;
+
+    #[test]
+    fn best_match_keeps_most_accurate_above_threshold() {
+        let results = vec![
+            MediaResult::new("1".to_string(), "Blazing".to_string(), "2001".to_string(), SERIES.clone(), false, 60),
+            MediaResult::new("2".to_string(), "Blazing Fast".to_string(), "2021".to_string(), SERIES.clone(), false, 100),
+            MediaResult::new("3".to_string(), "Blazing Fast!".to_string(), "2022".to_string(), SERIES.clone(), false, 99),
+        ];
+
+        let result = best_match(results, 70).unwrap();
+        assert_eq!(result.string(), "Blazing Fast (2021)");
+    }
+
+    #[test]
+    fn best_match_rejects_everything_below_threshold() {
+        let results = vec![
+            MediaResult::new("1".to_string(), "Something Else".to_string(), "2001".to_string(), SERIES.clone(), false, 20),
+        ];
+
+        assert!(best_match(results, 70).is_none());
+    }
+
+    #[test]
+    fn absolute_episodes_map_to_seasons() {
+        let seasons = vec![
+            SeasonDetails { number: 0, episode_count: 5 },
+            SeasonDetails { number: 2, episode_count: 24 },
+            SeasonDetails { number: 1, episode_count: 25 },
+            SeasonDetails { number: 3, episode_count: 100 },
+        ];
+
+        assert_eq!(absolute_to_season(1, &seasons), Some((1, 1)));
+        assert_eq!(absolute_to_season(25, &seasons), Some((1, 25)));
+        assert_eq!(absolute_to_season(26, &seasons), Some((2, 1)));
+        assert_eq!(absolute_to_season(137, &seasons), Some((3, 88)));
+        assert_eq!(absolute_to_season(150, &seasons), None);
+        assert_eq!(absolute_to_season(0, &seasons), None);
+    }
+
+    #[test]
+    fn lookup_uses_canonical_title() {
+        let server = TestServer::start(|_| {
+            r#"[
+                {"score": 0.9, "show": {"id": 1, "name": "Mushoku Tensei: Jobless Reincarnation", "premiered": "2021-01-11", "network": null, "image": null}},
+                {"score": 0.5, "show": {"id": 2, "name": "Mushoku", "premiered": null}}
+            ]"#.to_string()
+        });
+        let chain = ProviderChain::new(vec![Box::new(TvMaze::at(&server.url))]);
+
+        let (provider, result) = chain
+            .lookup("Mushoku Tensei Jobless Reincarnation", "", SERIES.clone(), DEFAULT_ACCURACY_THRESHOLD)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(provider.name(), TVMAZE);
+        assert_eq!(result.string(), "Mushoku Tensei: Jobless Reincarnation (2021)");
+        assert_eq!(server.requests(), vec!["/search/shows?q=Mushoku+Tensei+Jobless+Reincarnation"]);
+    }
+
+    #[test]
+    fn lookup_without_match() {
+        let server = TestServer::start(|_| "[]".to_string());
+        let chain = ProviderChain::new(vec![Box::new(TvMaze::at(&server.url))]);
+
+        assert!(chain.lookup("Blazing Fast", "", SERIES.clone(), DEFAULT_ACCURACY_THRESHOLD).unwrap().is_none());
+    }
+
+    #[test]
+    fn lookup_falls_back_in_order() {
+        let server = TestServer::start(|path| {
+            if path.starts_with("/search/shows") {
+                "[]".to_string()
+            } else {
+                r#"{"results": [{"id": 1, "name": "Blazing Fast", "first_air_date": "2021-04-01"}]}"#.to_string()
+            }
+        });
+        let chain = ProviderChain::new(vec![
+            Box::new(TvMaze::at(&server.url)),
+            Box::new(Tmdb::new(Some(server.url.clone()), "0123abcd".to_string())),
+        ]);
+
+        let (provider, result) = chain
+            .lookup("Blazing Fast", "", SERIES.clone(), DEFAULT_ACCURACY_THRESHOLD)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(provider.name(), TMDB);
+        assert_eq!(result.string(), "Blazing Fast (2021)");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn lookup_skips_providers_without_movies() {
+        let server = TestServer::start(|_| {
+            r#"{"results": [{"id": 78, "title": "Blade Runner", "release_date": "1982-06-25"}]}"#.to_string()
+        });
+        let chain = ProviderChain::new(vec![
+            Box::new(TvMaze::at(&server.url)),
+            Box::new(Tmdb::new(Some(server.url.clone()), "0123abcd".to_string())),
+        ]);
+
+        let (_, result) = chain
+            .lookup("Blade Runner", "", MOVIE.clone(), DEFAULT_ACCURACY_THRESHOLD)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(result.string(), "Blade Runner (1982)");
+        assert_eq!(server.requests(), vec!["/search/movie?api_key=0123abcd&query=Blade+Runner"]);
+    }
+
+    #[test]
+    fn from_names_validates_providers() {
+        let names = vec![ANILIST.to_string(), TMDB.to_string(), TVMAZE.to_string()];
+
+        assert!(ProviderChain::from_names(&names, None).is_err());
+        assert!(ProviderChain::from_names(&names, Some(Tmdb::new(None, "key".to_string()))).is_ok());
+        assert!(ProviderChain::from_names(&["imdb".to_string()], None).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use super::result::*;
+use super::search::{MetadataProvider, ANILIST};
+use super::strings::accuracy;
+use super::strings::normalize_string;
+
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use anyhow::Error;
+
+pub const ANILIST_API_URL: &str = "https://graphql.anilist.co";
+
+const SEARCH_QUERY: &str = "query ($search: String, $year: Int, $formats: [MediaFormat]) {
+  Page(perPage: 10) {
+    media(search: $search, seasonYear: $year, type: ANIME, format_in: $formats) {
+      id title { romaji english } startDate { year } format episodes duration
+    }
+  }
+}";
+
+const DETAILS_QUERY: &str = "query ($id: Int) {
+  Media(id: $id, type: ANIME) {
+    id title { romaji english } startDate { year } format episodes duration
+  }
+}";
+
+/// AniList backend, anime only. Every season is a separate entry on AniList.
+pub struct AniList {
+    pub api_url: String,
+}
+
+impl AniList {
+    pub fn new() -> AniList {
+        AniList::at(ANILIST_API_URL)
+    }
+
+    pub fn at(api_url: &str) -> AniList {
+        AniList { api_url: api_url.to_string() }
+    }
+
+    fn query<T: for<'de> Deserialize<'de>>(&self, query: &str, variables: serde_json::Value) -> Result<T, Error> {
+        let client = Client::new();
+        let response = client
+            .post(&self.api_url)
+            .json(&json!({ "query": query, "variables": variables }))
+            .send()?;
+        if !response.status().is_success() {
+            return Err(Error::msg(format!("Error: {}", response.status())));
+        }
+
+        let body: AniListResponse<T> = serde_json::from_str(&response.text()?)?;
+        Ok(body.data)
+    }
+}
+
+impl Default for AniList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetadataProvider for AniList {
+    fn name(&self) -> &str {
+        ANILIST
+    }
+
+    fn supports(&self, _media_type: &MediaType) -> bool {
+        true
+    }
+
+    fn search(&self, query: &str, year: &str, media_type: MediaType) -> Result<Vec<MediaResult>, Error> {
+        if cfg!(debug_assertions) {
+            println!("Searching AniList for '{}'", query);
+        }
+
+        let formats = if media_type == *MOVIE {
+            vec!["MOVIE"]
+        } else {
+            vec!["TV", "TV_SHORT", "ONA", "OVA"]
+        };
+        let year: Option<i32> = year.parse().ok();
+
+        let page: PageData = self.query(
+            SEARCH_QUERY,
+            json!({ "search": query, "year": year, "formats": formats }),
+        )?;
+
+        let mut results = Vec::new();
+        for media in page.page.media {
+            let Some(year) = media.start_date.year else { continue };
+
+            // Keep whichever title the filename was closest to, romaji for most fansubs
+            let best_title = [media.title.english, media.title.romaji]
+                .into_iter()
+                .flatten()
+                .map(|title| (accuracy(&normalize_string(query), &normalize_string(&title)), title))
+                .max_by_key(|(accuracy, _)| *accuracy);
+
+            if let Some((accuracy, title)) = best_title {
+                results.push(MediaResult::new(
+                    media.id.to_string(),
+                    title,
+                    year.to_string(),
+                    media_type.clone(),
+                    false,
+                    accuracy,
+                ));
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn details(&self, id: &str, media_type: MediaType) -> Result<MediaDetails, Error> {
+        let id: i64 = id.parse()?;
+        let data: MediaData = self.query(DETAILS_QUERY, json!({ "id": id }))?;
+        let media = data.media;
+
+        let seasons = match media.episodes {
+            Some(episodes) if media_type == *SERIES => vec![SeasonDetails { number: 1, episode_count: episodes }],
+            _ => Vec::new(),
+        };
+
+        Ok(MediaDetails {
+            id: media.id.to_string(),
+            title: media.title.english.or(media.title.romaji).unwrap_or_default(),
+            year: media.start_date.year.map(|year| year.to_string()).unwrap_or_default(),
+            media_type,
+            runtime: media.duration,
+            seasons,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct AniListResponse<T> {
+    pub data: T,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PageData {
+    #[serde(rename = "Page")]
+    pub page: Page,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MediaData {
+    #[serde(rename = "Media")]
+    pub media: Media,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Page {
+    #[serde(default)]
+    pub media: Vec<Media>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Media {
+    pub id: i64,
+    pub title: Title,
+    #[serde(rename = "startDate")]
+    pub start_date: FuzzyDate,
+    pub format: Option<String>,
+    pub episodes: Option<u32>,
+    pub duration: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Title {
+    pub romaji: Option<String>,
+    pub english: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct FuzzyDate {
+    pub year: Option<i32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::test_server::TestServer;
+
+    #[test]
+    fn search_anilist_keeps_closest_title() {
+        let server = TestServer::start(|_| {
+            r#"{"data": {"Page": {"media": [
+                {"id": 108465, "title": {"romaji": "Mushoku Tensei: Isekai Ittara Honki Dasu", "english": "Mushoku Tensei: Jobless Reincarnation"},
+                 "startDate": {"year": 2021}, "format": "TV", "episodes": 11, "duration": 24}
+            ]}}}"#.to_string()
+        });
+        let anilist = AniList::at(&server.url);
+
+        let results = anilist.search("Mushoku Tensei Isekai Ittara Honki Dasu", "", SERIES.clone()).unwrap();
+
+        assert_eq!(results[0].string(), "Mushoku Tensei: Isekai Ittara Honki Dasu (2021)");
+        assert_eq!(results[0].accuracy, 100);
+        assert_eq!(results[0].id, "108465");
+    }
+}
@@ -1,20 +1,115 @@
 use super::result::*;
+use super::search::{MetadataProvider, TVMAZE};
 use super::strings::accuracy;
 use super::strings::GETYEAR;
+use super::strings::normalize_string;
 
 use reqwest::blocking::Client;
 use serde::{Deserialize, Serialize};
 use anyhow::Error;
 
+pub const TVMAZE_API_URL: &str = "http://api.tvmaze.com";
+
+/// TVMaze backend, series only.
+pub struct TvMaze {
+    pub api_url: String,
+}
+
+impl TvMaze {
+    pub fn new() -> TvMaze {
+        TvMaze::at(TVMAZE_API_URL)
+    }
+
+    pub fn at(api_url: &str) -> TvMaze {
+        TvMaze { api_url: api_url.to_string() }
+    }
+}
+
+impl Default for TvMaze {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetadataProvider for TvMaze {
+    fn name(&self) -> &str {
+        TVMAZE
+    }
+
+    fn supports(&self, media_type: &MediaType) -> bool {
+        *media_type == *SERIES
+    }
+
+    fn search(&self, query: &str, year: &str, media_type: MediaType) -> Result<Vec<MediaResult>, Error> {
+        search_tvmaze_at(&self.api_url, query, year, media_type)
+    }
+
+    fn details(&self, id: &str, media_type: MediaType) -> Result<MediaDetails, Error> {
+        let url = format!("{}/shows/{}", self.api_url.trim_end_matches('/'), id);
+
+        let client = Client::new();
+        let response = client.get(&url).query(&[("embed", "seasons")]).send()?;
+        if !response.status().is_success() {
+            return Err(Error::msg(format!("Error: {}", response.status())));
+        }
+
+        let show: Show = serde_json::from_str(&response.text()?)?;
+        let year = GETYEAR
+            .captures(&show.premiered.unwrap_or_default())
+            .and_then(|captures| captures.get(1))
+            .map(|year_match| year_match.as_str().to_string())
+            .unwrap_or_default();
+
+        let seasons = show
+            .embedded
+            .map(|embedded| embedded.seasons)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|season| Some(SeasonDetails {
+                number: season.number?,
+                episode_count: season.episode_order?,
+            }))
+            .collect();
+
+        Ok(MediaDetails {
+            id: id.to_string(),
+            title: show.name,
+            year,
+            media_type,
+            runtime: show.runtime.or(show.average_runtime).map(|runtime| runtime as u32),
+            seasons,
+        })
+    }
+}
+
+impl TvMaze {
+    /// Every episode of a show, specials included.
+    pub fn episodes(&self, id: &str) -> Result<Vec<TvMazeEpisode>, Error> {
+        let url = format!("{}/shows/{}/episodes", self.api_url.trim_end_matches('/'), id);
+
+        let client = Client::new();
+        let response = client.get(&url).query(&[("specials", "1")]).send()?;
+        if !response.status().is_success() {
+            return Err(Error::msg(format!("Error: {}", response.status())));
+        }
+
+        Ok(serde_json::from_str(&response.text()?)?)
+    }
+}
+
 pub fn search_tvmaze(query: &str, year: &str, media_type: MediaType) -> Result<Vec<MediaResult>, Error>{
+    search_tvmaze_at(TVMAZE_API_URL, query, year, media_type)
+}
+
+pub fn search_tvmaze_at(api_url: &str, query: &str, year: &str, media_type: MediaType) -> Result<Vec<MediaResult>, Error>{
     let _ = year;
-    let url = format!("http://api.tvmaze.com/search/shows?q={}", query);
+    let url = format!("{}/search/shows", api_url.trim_end_matches('/'));
     if cfg!(debug_assertions) {
         println!("Searching TVMaze for '{}'", query);
     }
 
     let client = Client::new();
-    let response = client.get(&url).send()?;
+    let response = client.get(&url).query(&[("q", query)]).send()?;
     if !response.status().is_success() {
         return Err(Error::msg(format!("Error: {}", response.status())));
         }
@@ -25,11 +120,13 @@ pub fn search_tvmaze(query: &str, year: &str, media_type: MediaType) -> Result<V
     let mut results = Vec::new();
 
     for tv_maze_result in tv_maze_results {
-        if let Some(captures) = GETYEAR.captures(&tv_maze_result.show.premiered) {
+        let premiered = tv_maze_result.show.premiered.clone().unwrap_or_default();
+        if let Some(captures) = GETYEAR.captures(&premiered) {
             if let Some(year_match) = captures.get(1) {
-                let accuracy = accuracy(query, &tv_maze_result.show.name);
+                let accuracy = accuracy(&normalize_string(query), &normalize_string(&tv_maze_result.show.name));
                 results.push(
                     MediaResult::new(
+                    tv_maze_result.show.id.unwrap_or_default().to_string(),
                     tv_maze_result.show.name.clone(),
                     year_match.as_str().to_string(),
                     media_type.clone(),
@@ -45,8 +142,9 @@ pub fn search_tvmaze(query: &str, year: &str, media_type: MediaType) -> Result<V
 
 #[derive(Deserialize, Serialize)]
 pub struct Links {
-    pub previousepisode: Previousepisode,
-    pub self_: SelfLink,
+    pub previousepisode: Option<Previousepisode>,
+    #[serde(rename = "self")]
+    pub self_: Option<SelfLink>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -67,13 +165,13 @@ pub struct Externals {
 
 #[derive(Deserialize, Serialize)]
 pub struct Image {
-    pub medium: String,
-    pub original: String,
+    pub medium: Option<String>,
+    pub original: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct Network {
-    pub country: Country,
+    pub country: Option<Country>,
     pub id: Option<i32>,
     pub name: String,
 }
@@ -99,27 +197,58 @@ pub struct Schedule {
 
 #[derive(Deserialize, Serialize)]
 pub struct Show {
+    #[serde(rename = "_links")]
     pub links: Option<Links>,
-    pub externals: Externals,
+    pub externals: Option<Externals>,
+    #[serde(default)]
     pub genres: Vec<String>,
     pub id: Option<i32>,
-    pub image: Image,
-    pub language: String,
+    pub image: Option<Image>,
+    pub language: Option<String>,
     pub name: String,
-    pub network: Network,
-    pub premiered: String,
-    pub rating: Rating,
+    pub network: Option<Network>,
+    pub premiered: Option<String>,
+    pub rating: Option<Rating>,
     pub runtime: Option<i32>,
-    pub schedule: Schedule,
-    pub status: String,
-    pub summary: String,
-    pub r#type: String,
-    pub updated: Option<i32>,
-    pub url: String,
+    #[serde(rename = "averageRuntime")]
+    pub average_runtime: Option<i32>,
+    pub schedule: Option<Schedule>,
+    pub status: Option<String>,
+    pub summary: Option<String>,
+    pub r#type: Option<String>,
+    pub updated: Option<i64>,
+    pub url: Option<String>,
+    #[serde(rename = "webChannel")]
     pub web_channel: Option<serde_json::Value>,
     pub weight: Option<i32>,
+    #[serde(rename = "_embedded")]
+    pub embedded: Option<Embedded>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Embedded {
+    #[serde(default)]
+    pub seasons: Vec<Season>,
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct Season {
+    pub id: Option<i32>,
+    pub number: Option<u32>,
+    #[serde(rename = "episodeOrder")]
+    pub episode_order: Option<u32>,
+    #[serde(rename = "premiereDate")]
+    pub premiere_date: Option<String>,
+}
+
+/// An episode of a show, specials have no number and unscheduled episodes no air date.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TvMazeEpisode {
+    pub season: u32,
+    pub number: Option<u32>,
+    pub name: Option<String>,
+    pub airdate: Option<String>,
+}
 
 #[derive(Deserialize ,Serialize)]
 pub struct TvMazeResult {
@@ -32,7 +32,7 @@ pub static YEAR: Lazy<Regex> = Lazy::new(|| Regex::new(&format!(r"^(.+?\b){}\b",
 pub static JOINEDEPISEASON: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(.+?\b)(\d)(\d{2})\b").unwrap());
 pub static PARTNUM: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(.+?\b)(\d{1,2})\b").unwrap());
 
-pub fn NormalizeString(input: &str) -> String {
+pub fn normalize_string(input: &str) -> String {
     let mut output = input.to_lowercase();
     output = NONALPHA.replace_all(&output, " ").to_string();
     output = ENCODINGS.replace_all(&output, "").to_string();
@@ -45,11 +45,11 @@ pub fn abs(n: i64) -> i64 {
     if n < 0 {
         return -n
     }
-    return n
+    n
 }
 
 pub fn accuracy(a: &str, b: &str) -> i64 {
-    return 100 - dist(a, b)
+    100 - dist(a, b)
 }
 
 pub fn dist(a: &str, b: &str) -> i64 {
@@ -72,8 +72,8 @@ pub fn dist(a: &str, b: &str) -> i64 {
   let mut cur = vec![0; len_b];
 
   // initialize string b
-  for i in 1..len_b {
-      cur[i] = i;
+  for (i, distance) in cur.iter_mut().enumerate().skip(1) {
+      *distance = i;
   }
 
   // calculate edit distance
@@ -101,5 +101,5 @@ pub fn dist(a: &str, b: &str) -> i64 {
 
 pub fn is_near(a: &str, b: &str) -> bool {
     let lendiff = abs(a.chars().count() as i64 - b.chars().count() as i64);
-    return dist(a, b) - lendiff <= 5;
+    dist(a, b) - lendiff <= 5
 }
\ No newline at end of file